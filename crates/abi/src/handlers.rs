@@ -0,0 +1,71 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use unit_utils::{err::bail, Result};
+use wasmparser::{Parser, Payload};
+
+/// Name of the wasm custom section handler attributes register themselves in.
+/// Every attribute emits its own length-prefixed record; the linker concatenates them.
+pub static HANDLERS_SECTION_NAME: &str = "unit_handlers";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Handler {
+    Http {
+        method: String,
+        path: String,
+        export: String,
+    },
+    /// A `#[unit::topic]` whose name is a pattern (see `topics`).
+    Topic { pattern: String, export: String },
+}
+
+fn handler_options() -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+pub fn encode_handler_record(handler: &Handler) -> Result<Vec<u8>> {
+    let data = handler_options().serialize(handler)?;
+
+    let mut bytes = Vec::with_capacity(4 + data.len());
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&data);
+
+    Ok(bytes)
+}
+
+fn decode_handler_records(mut data: &[u8], handlers: &mut Vec<Handler>) -> Result<()> {
+    while !data.is_empty() {
+        let Some(len_bytes) = data.get(..4) else {
+            bail!("Truncated handler record length");
+        };
+
+        let len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
+        let Some(record) = data.get(4..4 + len as usize) else {
+            bail!("Truncated handler record");
+        };
+
+        handlers.push(handler_options().deserialize(record)?);
+        data = &data[4 + len as usize..];
+    }
+
+    Ok(())
+}
+
+pub fn decode_handlers(bytes: &[u8]) -> Result<Vec<Handler>> {
+    let mut handlers = vec![];
+
+    for payload in Parser::new(0).parse_all(bytes) {
+        let Payload::CustomSection(reader) = payload? else {
+            continue;
+        };
+
+        if reader.name() != HANDLERS_SECTION_NAME {
+            continue;
+        }
+
+        decode_handler_records(reader.data(), &mut handlers)?;
+    }
+
+    Ok(handlers)
+}
@@ -1,25 +1,89 @@
+use bincode::Options;
 use serde::{Deserialize, Serialize};
 use unit_utils::{err::bail, Result};
+use wasmparser::{Parser, Payload};
 
 use crate::magic::{find_magic, MAGIC};
 
+/// Name of the wasm custom section `application!` stores the header in.
+pub static ABI_SECTION_NAME: &str = "unit_abi";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AbiHeader {
     pub name: String,
 }
 
+fn header_options() -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
 pub fn decode_abi_header(bytes: &[u8]) -> Result<AbiHeader> {
-    let Some(magic_index) = find_magic(&bytes) else {
+    if let Some(header) = decode_abi_section(bytes)? {
+        return Ok(header);
+    }
+
+    decode_legacy_abi_header(bytes)
+}
+
+/// Reads the header from the `unit_abi` custom section. Returns `None` if the module has no such section.
+pub fn decode_abi_section(bytes: &[u8]) -> Result<Option<AbiHeader>> {
+    let mut section: Option<&[u8]> = None;
+
+    for payload in Parser::new(0).parse_all(bytes) {
+        let Payload::CustomSection(reader) = payload? else {
+            continue;
+        };
+
+        if reader.name() != ABI_SECTION_NAME {
+            continue;
+        }
+
+        if section.is_some() {
+            bail!("Duplicate {} section", ABI_SECTION_NAME);
+        }
+
+        section = Some(reader.data());
+    }
+
+    let Some(data) = section else {
+        return Ok(None);
+    };
+
+    let header = header_options().deserialize(data)?;
+
+    Ok(Some(header))
+}
+
+/// Locates the header through the magic marker emitted by older versions of `application!`.
+pub fn decode_legacy_abi_header(bytes: &[u8]) -> Result<AbiHeader> {
+    let Some(magic_index) = find_magic(bytes) else {
         bail!("Failed to locate magic");
     };
 
-    let len = u16::from_be_bytes([bytes[magic_index + 8], bytes[magic_index + 9]]);
-    let data = &bytes[magic_index + 10..magic_index + 10 + len as usize];
+    let len_start = magic_index + MAGIC.len();
+    let Some(len_bytes) = bytes.get(len_start..len_start + 2) else {
+        bail!("Truncated ABI header length");
+    };
+
+    let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+    let Some(data) = bytes.get(len_start + 2..len_start + 2 + len) else {
+        bail!("Truncated ABI header");
+    };
+
     let header = bincode::deserialize(data)?;
 
     Ok(header)
 }
 
+/// Encodes the contents of the `unit_abi` custom section.
+pub fn encode_abi_section(header: &AbiHeader) -> Result<Vec<u8>> {
+    let data = header_options().serialize(header)?;
+    Ok(data)
+}
+
+/// Encodes the legacy magic-prefixed header.
 pub fn encode_abi_header(header: &AbiHeader) -> Result<Vec<u8>> {
     let data = bincode::serialize(header)?;
 
@@ -30,3 +94,100 @@ pub fn encode_abi_header(header: &AbiHeader) -> Result<Vec<u8>> {
 
     Ok(bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static WASM_PREAMBLE: &[u8] = &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+    fn custom_section(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut contents = vec![name.len() as u8];
+        contents.extend_from_slice(name.as_bytes());
+        contents.extend_from_slice(data);
+
+        let mut section = vec![0x00, contents.len() as u8];
+        section.extend_from_slice(&contents);
+        section
+    }
+
+    fn module(sections: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = WASM_PREAMBLE.to_vec();
+        for section in sections {
+            bytes.extend_from_slice(section);
+        }
+        bytes
+    }
+
+    fn header() -> AbiHeader {
+        AbiHeader {
+            name: "hello".to_owned(),
+        }
+    }
+
+    #[test]
+    fn section_roundtrip() {
+        let data = encode_abi_section(&header()).unwrap();
+        let bytes = module(&[custom_section(ABI_SECTION_NAME, &data)]);
+
+        let decoded = decode_abi_section(&bytes).unwrap().unwrap();
+        assert_eq!(decoded.name, "hello");
+        assert_eq!(decode_abi_header(&bytes).unwrap().name, "hello");
+    }
+
+    #[test]
+    fn missing_section() {
+        let bytes = module(&[custom_section("other", b"data")]);
+
+        assert!(decode_abi_section(&bytes).unwrap().is_none());
+        assert!(decode_abi_header(&bytes).is_err());
+    }
+
+    #[test]
+    fn duplicate_section() {
+        let data = encode_abi_section(&header()).unwrap();
+        let section = custom_section(ABI_SECTION_NAME, &data);
+        let bytes = module(&[section.clone(), section]);
+
+        assert!(decode_abi_section(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_section() {
+        let data = encode_abi_section(&header()).unwrap();
+        let mut bytes = module(&[custom_section(ABI_SECTION_NAME, &data)]);
+        bytes.truncate(bytes.len() - 3);
+
+        assert!(decode_abi_section(&bytes).is_err());
+    }
+
+    #[test]
+    fn malformed_section_data() {
+        // a string length far past the end of the data
+        let bytes = module(&[custom_section(ABI_SECTION_NAME, &[0xff; 8])]);
+        assert!(decode_abi_section(&bytes).is_err());
+
+        let mut data = encode_abi_section(&header()).unwrap();
+        data.push(0);
+        let bytes = module(&[custom_section(ABI_SECTION_NAME, &data)]);
+        assert!(decode_abi_section(&bytes).is_err());
+    }
+
+    #[test]
+    fn legacy_roundtrip() {
+        let mut bytes = b"padding".to_vec();
+        bytes.extend_from_slice(&encode_abi_header(&header()).unwrap());
+        bytes.extend_from_slice(b"trailing");
+
+        assert_eq!(decode_legacy_abi_header(&bytes).unwrap().name, "hello");
+    }
+
+    #[test]
+    fn legacy_truncated() {
+        let encoded = encode_abi_header(&header()).unwrap();
+
+        assert!(decode_legacy_abi_header(&encoded[..MAGIC.len() + 1]).is_err());
+        assert!(decode_legacy_abi_header(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_legacy_abi_header(b"no magic here").is_err());
+    }
+}
@@ -1,3 +1,6 @@
+pub mod handlers;
 pub mod header;
 pub mod magic;
-
+pub mod requests;
+pub mod surface;
+pub mod topics;
@@ -0,0 +1,77 @@
+use unit_utils::{err::bail, Result};
+
+use crate::topics::escape_export_name;
+
+/// Request names are non-empty printable ASCII without spaces.
+pub fn validate_request_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Request name is empty");
+    }
+
+    if !name.bytes().all(|byte| byte.is_ascii_graphic()) {
+        bail!(
+            "Request name {:?} must be printable ASCII without spaces",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+/// Export name of the `#[unit::request]` handler for `name`, escaped like topic exports.
+pub fn request_export_name(name: &str) -> String {
+    escape_export_name("unit_request_", name)
+}
+
+/// Export name modules built before the escaping gave the handler for `name`. Names differing
+/// only in case or `-`/`_` shared it.
+pub fn legacy_request_export_name(name: &str) -> String {
+    format!("unit_request_{}", name.to_lowercase().replace('-', "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_names() {
+        for name in ["event-count", "cart.get", "get_cart", "Get-Cart", "a/b"] {
+            assert!(validate_request_name(name).is_ok(), "{}", name);
+        }
+
+        for name in ["", "get cart", "caf\u{e9}", "a\nb"] {
+            assert!(validate_request_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn export_names() {
+        let cases = [
+            ("event-count", "unit_request_event_2dcount"),
+            ("get_cart", "unit_request_get__cart"),
+            ("Get-Cart", "unit_request_Get_2dCart"),
+            ("cart.get", "unit_request_cart_2eget"),
+        ];
+
+        for (name, export) in cases {
+            assert_eq!(request_export_name(name), export, "{}", name);
+        }
+    }
+
+    #[test]
+    fn export_names_are_distinct() {
+        let names = ["get-cart", "get_cart", "Get-Cart", "get.cart", "getcart"];
+
+        for (i, a) in names.iter().enumerate() {
+            for b in &names[i + 1..] {
+                assert_ne!(
+                    request_export_name(a),
+                    request_export_name(b),
+                    "{} {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiType {
+    I32,
+    I64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AbiFunction {
+    pub name: &'static str,
+    pub params: &'static [AbiType],
+    pub results: &'static [AbiType],
+}
+
+impl AbiFunction {
+    pub const fn new(
+        name: &'static str,
+        params: &'static [AbiType],
+        results: &'static [AbiType],
+    ) -> Self {
+        Self {
+            name,
+            params,
+            results,
+        }
+    }
+}
+
+/// Import module under which the node provides the unit host functions and the shared memory.
+pub static HOST_MODULE: &str = "env";
+pub static HOST_MEMORY: &str = "memory";
+
+/// Import modules satisfied by the node's WASI/WASIX environment.
+pub static WASI_MODULES: &[&str] = &[
+    "wasi",
+    "wasi_unstable",
+    "wasi_snapshot_preview1",
+    "wasix_32v1",
+    "wasix_64v1",
+];
+
+/// Functions the node exposes to guests. Keep in sync with the imports built in the node runtime.
+pub static HOST_FUNCTIONS: &[AbiFunction] = &[
+    AbiFunction::new("unit_log", &[AbiType::I32, AbiType::I32], &[]),
+    AbiFunction::new("unit_send_message", &[AbiType::I32, AbiType::I32], &[]),
+    AbiFunction::new(
+        "unit_http_respond",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new(
+        "unit_authorize_respond",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new(
+        "unit_connection_info",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new("unit_request_respond", &[AbiType::I32, AbiType::I32], &[]),
+    AbiFunction::new(
+        "unit_crossbar_replay",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new(
+        "unit_crossbar_publish",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new(
+        "unit_presence_update",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new("unit_presence_list", &[], &[AbiType::I32]),
+    AbiFunction::new("unit_host_result", &[AbiType::I32, AbiType::I32], &[]),
+];
+
+/// Exports every guest must provide.
+pub static REQUIRED_EXPORTS: &[AbiFunction] = &[
+    AbiFunction::new("_start", &[], &[]),
+    AbiFunction::new("unit_alloc_bytes", &[AbiType::I32], &[AbiType::I32]),
+    AbiFunction::new("unit_free_bytes", &[AbiType::I32, AbiType::I32], &[]),
+];
+
+/// Exports the node calls when present.
+pub static OPTIONAL_EXPORTS: &[AbiFunction] = &[
+    AbiFunction::new("unit_init", &[], &[AbiType::I32]),
+    AbiFunction::new("unit_cleanup", &[], &[]),
+    AbiFunction::new(
+        "unit_message",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+    AbiFunction::new("unit_event", &[AbiType::I32, AbiType::I32], &[AbiType::I32]),
+    AbiFunction::new(
+        "unit_authorize",
+        &[AbiType::I32, AbiType::I32],
+        &[AbiType::I32],
+    ),
+];
+
+/// Prefixed handler exports generated by the framework attributes (`#[unit::topic]`, `#[unit::http]`,
+/// `#[unit::request]`).
+pub static PREFIXED_EXPORTS: &[(&str, AbiFunction)] = &[
+    (
+        "unit_topic_",
+        AbiFunction::new(
+            "unit_topic_*",
+            &[AbiType::I32, AbiType::I32],
+            &[AbiType::I32],
+        ),
+    ),
+    (
+        "unit_pattern_",
+        AbiFunction::new(
+            "unit_pattern_*",
+            &[AbiType::I32, AbiType::I32],
+            &[AbiType::I32],
+        ),
+    ),
+    (
+        "unit_http_",
+        AbiFunction::new(
+            "unit_http_*",
+            &[AbiType::I32, AbiType::I32],
+            &[AbiType::I32],
+        ),
+    ),
+    (
+        "unit_request_",
+        AbiFunction::new(
+            "unit_request_*",
+            &[AbiType::I32, AbiType::I32],
+            &[AbiType::I32],
+        ),
+    ),
+];
+
+pub fn find_host_function(name: &str) -> Option<&'static AbiFunction> {
+    HOST_FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+pub fn find_export(name: &str) -> Option<&'static AbiFunction> {
+    if let Some((_, export)) = PREFIXED_EXPORTS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+    {
+        return Some(export);
+    }
+
+    REQUIRED_EXPORTS
+        .iter()
+        .chain(OPTIONAL_EXPORTS.iter())
+        .find(|f| f.name == name)
+}
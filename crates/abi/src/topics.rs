@@ -0,0 +1,278 @@
+use unit_utils::{err::bail, Result};
+
+/// Topics are dot-separated segments. In a pattern, `*` matches one segment, `{name}` matches
+/// one segment and captures it as `name`, and a trailing `**` matches one or more segments.
+/// `*` and `**` captures are keyed by their segment index.
+pub fn is_topic_pattern(topic: &str) -> bool {
+    topic.contains('*') || topic.contains('{')
+}
+
+/// `prefix` followed by `name` with ASCII letters and digits kept, `_` doubled and any other
+/// byte turned into `_` and two hex digits, so no two names share an export.
+pub(crate) fn escape_export_name(prefix: &str, name: &str) -> String {
+    let mut export = String::from(prefix);
+
+    for byte in name.bytes() {
+        match byte {
+            b'_' => export.push_str("__"),
+            byte if byte.is_ascii_alphanumeric() => export.push(byte as char),
+            byte => export.push_str(&format!("_{:02x}", byte)),
+        }
+    }
+
+    export
+}
+
+/// Export name of the handler for the exact topic `topic`.
+pub fn topic_export_name(topic: &str) -> String {
+    escape_export_name("unit_topic_", topic)
+}
+
+/// Export name modules built before the escaping gave the handler for `topic`. Topics
+/// differing only in case or `-`/`_` shared it.
+pub fn legacy_topic_export_name(topic: &str) -> String {
+    format!("unit_topic_{}", topic.to_lowercase().replace('-', "_"))
+}
+
+/// The handler a module exports for the exact topic `topic`, by its current name or else by
+/// its legacy one.
+pub fn find_topic_export(topic: &str, has_export: impl Fn(&str) -> bool) -> Option<String> {
+    [topic_export_name(topic), legacy_topic_export_name(topic)]
+        .into_iter()
+        .find(|export| has_export(export))
+}
+
+fn capture_name(segment: &str) -> Option<&str> {
+    segment.strip_prefix('{')?.strip_suffix('}')
+}
+
+pub fn validate_topic_pattern(pattern: &str) -> Result<()> {
+    let segments: Vec<&str> = pattern.split('.').collect();
+    let mut names: Vec<&str> = vec![];
+
+    for (index, segment) in segments.iter().enumerate() {
+        if segment.is_empty() {
+            bail!("Empty segment in topic pattern {}", pattern);
+        }
+
+        if *segment == "**" {
+            if index != segments.len() - 1 {
+                bail!("`**` must be the last segment of {}", pattern);
+            }
+            continue;
+        }
+
+        if *segment == "*" {
+            continue;
+        }
+
+        if let Some(name) = capture_name(segment) {
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                bail!("Invalid capture {} in topic pattern {}", segment, pattern);
+            }
+            if names.contains(&name) {
+                bail!("Duplicate capture {} in topic pattern {}", segment, pattern);
+            }
+            names.push(name);
+            continue;
+        }
+
+        if segment.contains(['*', '{', '}']) {
+            bail!("Invalid segment {} in topic pattern {}", segment, pattern);
+        }
+    }
+
+    Ok(())
+}
+
+/// The captured segments if `topic` matches `pattern`. Wildcards never match empty segments.
+pub fn match_topic(pattern: &str, topic: &str) -> Option<Vec<(String, String)>> {
+    if topic.split('.').any(|segment| segment.is_empty()) {
+        return None;
+    }
+
+    let mut params = vec![];
+    let mut topic_segments = topic.split('.');
+
+    for (index, segment) in pattern.split('.').enumerate() {
+        if segment == "**" {
+            let rest: Vec<&str> = topic_segments.collect();
+            if rest.is_empty() {
+                return None;
+            }
+
+            params.push((index.to_string(), rest.join(".")));
+            return Some(params);
+        }
+
+        let value = topic_segments.next()?;
+
+        if segment == "*" {
+            params.push((index.to_string(), value.to_owned()));
+        } else if let Some(name) = capture_name(segment) {
+            params.push((name.to_owned(), value.to_owned()));
+        } else if segment != value {
+            return None;
+        }
+    }
+
+    if topic_segments.next().is_some() {
+        return None;
+    }
+
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn valid_patterns() {
+        let cases = [
+            "room.*",
+            "room.{id}.chat",
+            "room.{id}.{user_id}",
+            "*.*",
+            "events.**",
+            "**",
+            "a.*.{b}.**",
+        ];
+
+        for pattern in cases {
+            assert!(validate_topic_pattern(pattern).is_ok(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let cases = [
+            "",
+            ".room",
+            "room.",
+            "room..chat",
+            "**.room",
+            "a.**.b",
+            "room.{}",
+            "room.{id",
+            "room.id}",
+            "room.{a-b}",
+            "room.{id}.{id}",
+            "room*",
+            "r{id}",
+            "***",
+        ];
+
+        for pattern in cases {
+            assert!(validate_topic_pattern(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let cases = [
+            ("room.*", "room.1", Some(params(&[("1", "1")]))),
+            ("room.*", "room", None),
+            ("room.*", "room.1.chat", None),
+            ("room.*", "lobby.1", None),
+            (
+                "room.{id}.chat",
+                "room.42.chat",
+                Some(params(&[("id", "42")])),
+            ),
+            ("room.{id}.chat", "room.42.voice", None),
+            ("*.{b}", "x.y", Some(params(&[("0", "x"), ("b", "y")]))),
+            ("events.**", "events.a", Some(params(&[("1", "a")]))),
+            ("events.**", "events.a.b.c", Some(params(&[("1", "a.b.c")]))),
+            ("events.**", "events", None),
+            ("a.b", "a.b", Some(vec![])),
+            ("a.b", "a.c", None),
+        ];
+
+        for (pattern, topic, expected) in cases {
+            assert_eq!(
+                match_topic(pattern, topic),
+                expected,
+                "{} {}",
+                pattern,
+                topic
+            );
+        }
+    }
+
+    #[test]
+    fn empty_segments_never_match() {
+        let cases = [
+            ("room.*", "room."),
+            ("room.{id}.chat", "room..chat"),
+            ("*.b", ".b"),
+            ("events.**", "events."),
+            ("events.**", "events.a..b"),
+            ("events.**", "events.a."),
+        ];
+
+        for (pattern, topic) in cases {
+            assert_eq!(match_topic(pattern, topic), None, "{} {}", pattern, topic);
+        }
+    }
+
+    #[test]
+    fn export_names() {
+        let cases = [
+            ("test", "unit_topic_test"),
+            ("Test", "unit_topic_Test"),
+            ("a.b", "unit_topic_a_2eb"),
+            ("a_b", "unit_topic_a__b"),
+            ("a-b", "unit_topic_a_2db"),
+            ("a_2eb", "unit_topic_a__2eb"),
+        ];
+
+        for (topic, name) in cases {
+            assert_eq!(topic_export_name(topic), name, "{}", topic);
+        }
+    }
+
+    #[test]
+    fn legacy_exports() {
+        let exports = ["unit_topic_orders_eu", "unit_topic_Test", "unit_topic_test"];
+        let has_export = |export: &str| exports.contains(&export);
+
+        let cases = [
+            ("orders-eu", Some("unit_topic_orders_eu")),
+            ("Orders-EU", Some("unit_topic_orders_eu")),
+            ("orders_eu", Some("unit_topic_orders_eu")),
+            ("Test", Some("unit_topic_Test")),
+            ("TEST", Some("unit_topic_test")),
+            ("orders.eu", None),
+        ];
+
+        for (topic, export) in cases {
+            assert_eq!(
+                find_topic_export(topic, has_export).as_deref(),
+                export,
+                "{}",
+                topic
+            );
+        }
+    }
+
+    #[test]
+    fn export_names_are_distinct() {
+        let topics = [
+            "a.b", "a_b", "a-b", "a__b", "a_2eb", "A.b", "ab", "a.b.", "a", "", "a_", "a._",
+        ];
+
+        for (i, a) in topics.iter().enumerate() {
+            for b in &topics[i + 1..] {
+                assert_ne!(topic_export_name(a), topic_export_name(b), "{} {}", a, b);
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use unit_utils::{
     env, lazy_static,
-    shared_config::{self, resolve_storage_path, ConfigRedis},
+    shared_config::{self, resolve_storage_path, ConfigDurable, ConfigPubSub, ConfigTls},
 };
 
 lazy_static! {
@@ -10,9 +10,20 @@ lazy_static! {
 #[derive(Debug)]
 pub struct Config {
     pub grpc_port: u32,
+    pub metrics_port: u32,
     pub grpc_api_key: String,
+    pub tls: Option<ConfigTls>,
     pub storage_location: String,
-    pub redis: ConfigRedis,
+    pub pubsub: ConfigPubSub,
+    pub deploy_dry_run: bool,
+    pub otlp_endpoint: Option<String>,
+    pub request_timeout_ms: u64,
+    /// Upper bound for the timeout a caller can ask for.
+    pub max_request_timeout_ms: u64,
+    pub durable: ConfigDurable,
+    /// How long an idempotency key keeps a push from being repeated.
+    pub idempotency_ttl_ms: u64,
+    pub push_batch_limit: usize,
 }
 
 impl Config {
@@ -20,19 +31,41 @@ impl Config {
         env::load_env();
 
         let grpc_port = env::value_or_default("UNIT_GRPC_PORT", 6448u32);
+        let metrics_port = env::value_or_default("UNIT_METRICS_PORT", 6449u32);
         let grpc_api_key: String = env::required_value("UNIT_GRPC_API_KEY");
+        let tls = shared_config::resolve_tls("UNIT_GRPC");
 
         let storage_location = resolve_storage_path();
+        let deploy_dry_run = env::value_or_default("UNIT_DEPLOY_DRY_RUN", true);
+        let otlp_endpoint = env::optional_str("UNIT_OTLP_ENDPOINT");
+        let request_timeout_ms = env::value_or_default("UNIT_REQUEST_TIMEOUT_MS", 5000u64);
+        let max_request_timeout_ms = env::value_or_default("UNIT_MAX_REQUEST_TIMEOUT_MS", 60000u64);
+        if request_timeout_ms == 0 {
+            panic!("UNIT_REQUEST_TIMEOUT_MS must be greater than 0");
+        }
+        if request_timeout_ms > max_request_timeout_ms {
+            panic!("UNIT_REQUEST_TIMEOUT_MS must not exceed UNIT_MAX_REQUEST_TIMEOUT_MS");
+        }
+        let durable = shared_config::resolve_durable();
+        let idempotency_ttl_ms = env::value_or_default("UNIT_IDEMPOTENCY_TTL_MS", 3600000u64);
+        let push_batch_limit = env::value_or_default("UNIT_PUSH_BATCH_LIMIT", 1000usize);
 
-        let Some(redis_config) = shared_config::resolve_redis() else {
-            panic!("Failed to resolve redis config");
-        };
+        let pubsub = shared_config::resolve_pubsub();
 
         Self {
             grpc_port,
+            metrics_port,
             grpc_api_key,
+            tls,
             storage_location,
-            redis: redis_config,
+            pubsub,
+            deploy_dry_run,
+            otlp_endpoint,
+            request_timeout_ms,
+            max_request_timeout_ms,
+            durable,
+            idempotency_ttl_ms,
+            push_batch_limit,
         }
     }
 
@@ -2,13 +2,17 @@ use crate::config::CONFIG;
 
 mod auth;
 mod config;
+mod metrics;
 mod server;
 mod service;
+mod validate;
 
+use log::error;
+use metrics::serve_metrics;
 use server::start_grpc_api;
 use unit_index::Index;
-use unit_pubsub::PubSub;
-use unit_utils::Result;
+use unit_telemetry::{init_tracing, shutdown_tracing};
+use unit_utils::{err::bail, Result};
 
 fn setup_logger() {
     if std::env::var("RUST_LOG").is_err() {
@@ -20,12 +24,26 @@ fn setup_logger() {
 #[tokio::main]
 async fn main() -> Result<()> {
     setup_logger();
+    init_tracing("unit-api", CONFIG.otlp_endpoint.as_deref())?;
 
     let index = Index::load(CONFIG.storage_location.clone())?;
-    let pubsub = PubSub::connect(CONFIG.redis.clone()).await?;
+    let pubsub = unit_pubsub::connect(&CONFIG.pubsub).await?;
+    if CONFIG.durable.is_enabled() && pubsub.redis().is_none() {
+        bail!("UNIT_DURABLE_TOPICS needs the Redis pub/sub backend");
+    }
+
+    let metrics_addr = format!("0.0.0.0:{port}", port = CONFIG.metrics_port);
+    let metrics_pubsub = pubsub.clone();
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics(metrics_addr, metrics_pubsub).await {
+            error!("metrics server error {:?}", e);
+        }
+    });
 
     let addr = format!("0.0.0.0:{port}", port = CONFIG.grpc_port);
     start_grpc_api(addr, index, pubsub).await?;
 
+    shutdown_tracing();
+
     Ok(())
 }
@@ -0,0 +1,114 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use log::{error, info};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use unit_pubsub::{PubSubHealth, SharedPubSub};
+use unit_utils::{lazy_static, Result};
+
+lazy_static! {
+    pub static ref DEPLOYS: IntCounterVec = register_int_counter_vec!(
+        "unit_api_deploys_total",
+        "App deploys by outcome",
+        &["app", "result"]
+    )
+    .unwrap();
+    pub static ref PUSH_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "unit_api_push_requests_total",
+        "Crossbar push RPCs by outcome",
+        &["rpc", "result"]
+    )
+    .unwrap();
+    pub static ref PUSH_SECONDS: HistogramVec = register_histogram_vec!(
+        "unit_api_push_seconds",
+        "Crossbar push RPC latency",
+        &["rpc"]
+    )
+    .unwrap();
+    pub static ref PUSHED_MESSAGES: IntCounter = register_int_counter!(
+        "unit_api_pushed_messages_total",
+        "Messages published to the crossbar"
+    )
+    .unwrap();
+    pub static ref PUBLISH_FAILURES: IntCounter = register_int_counter!(
+        "unit_api_publish_failures_total",
+        "Crossbar messages that failed to publish"
+    )
+    .unwrap();
+    pub static ref ACTIVE_SUBSCRIBERS: IntGauge = register_int_gauge!(
+        "unit_api_active_subscribers",
+        "Open crossbar Subscribe streams"
+    )
+    .unwrap();
+}
+
+pub fn result_label<T, E>(result: &std::result::Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    }
+}
+
+async fn metrics_handler(
+    request: Request<Body>,
+    pubsub: SharedPubSub,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let mut response = Response::new(Body::empty());
+
+    // the pub/sub connection state; unavailable while it's reconnecting
+    if request.uri().path() == "/health" {
+        let health = pubsub.health();
+        if health != PubSubHealth::Connected {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        *response.body_mut() = Body::from(health.as_str());
+        return Ok(response);
+    }
+
+    if request.uri().path() != "/metrics" {
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("failed to encode metrics {:?}", e);
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(response);
+    }
+
+    *response.body_mut() = Body::from(buffer);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, encoder.format_type().parse().unwrap());
+
+    Ok(response)
+}
+
+pub async fn serve_metrics(addr: String, pubsub: SharedPubSub) -> Result<()> {
+    let addr: SocketAddr = addr.parse()?;
+
+    let make_service = make_service_fn(move |_| {
+        let pubsub = pubsub.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                metrics_handler(request, pubsub.clone())
+            }))
+        }
+    });
+
+    info!("metrics listening on http://{}/metrics", &addr);
+
+    Server::bind(&addr).serve(make_service).await?;
+
+    Ok(())
+}
@@ -1,31 +1,97 @@
-use log::info;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures::{stream, Stream, StreamExt};
+use log::{error, info};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
 use tonic::transport::Server;
 use unit_index::Index;
-use unit_pubsub::PubSub;
+use unit_pubsub::SharedPubSub;
+use unit_tls::{load_server_config, ServerConfig};
 use unit_utils::Result;
 
 use crate::{
     auth,
+    config::CONFIG,
     service::admin::{AdminServer, AdminService},
     service::crossbar::{CrossbarServer, CrossbarService},
 };
 
-pub async fn start_grpc_api(addr: String, index: Index, pubsub: PubSub) -> Result<()> {
-    let addr = addr.parse()?;
+/// Accepts TCP connections and yields them once the TLS handshake is done.
+/// Handshakes run concurrently so a slow client can't hold up the others.
+fn tls_incoming(
+    listener: TcpListener,
+    tls_config: Arc<ServerConfig>,
+) -> impl Stream<Item = std::io::Result<TlsStream<TcpStream>>> {
+    let acceptor = TlsAcceptor::from(tls_config);
+    let (tx, rx) = mpsc::channel::<std::io::Result<TlsStream<TcpStream>>>(32);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("failed to accept connection {:?}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Err(e) => info!("tls handshake with {} failed: {}", remote_addr, e),
+                }
+            });
+        }
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+    .boxed()
+}
+
+pub async fn start_grpc_api(addr: String, index: Index, pubsub: SharedPubSub) -> Result<()> {
+    let addr: SocketAddr = addr.parse()?;
 
-    let admin_service = AdminService::new(index);
+    let admin_service = AdminService::new(index, pubsub.clone());
     let admin_server = AdminServer::with_interceptor(admin_service, auth::check_auth);
 
     let crossbar_service = CrossbarService::new(pubsub);
     let crossbar_server = CrossbarServer::with_interceptor(crossbar_service, auth::check_auth);
 
-    info!("grpc api listening on http://{}", &addr);
-
-    Server::builder()
+    let router = Server::builder()
         .add_service(admin_server)
-        .add_service(crossbar_server)
-        .serve(addr)
-        .await?;
+        .add_service(crossbar_server);
+
+    match CONFIG.tls.as_ref() {
+        Some(tls) => {
+            let tls_config = load_server_config(tls, &[b"h2"])?;
+            let listener = TcpListener::bind(addr).await?;
+
+            info!("grpc api listening on https://{}", &addr);
+            if tls.client_ca_path.is_some() {
+                info!("grpc api requires client certificates");
+            }
+
+            router
+                .serve_with_incoming(tls_incoming(listener, tls_config))
+                .await?;
+        }
+        None => {
+            info!("grpc api listening on http://{}", &addr);
+
+            router.serve(addr).await?;
+        }
+    }
 
     Ok(())
 }
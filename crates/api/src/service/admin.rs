@@ -7,22 +7,29 @@ use std::{path::PathBuf, sync::Mutex};
 use log::info;
 use tonic::{Request, Response, Status};
 use unit_abi::header::decode_abi_header;
-use unit_index::Index;
+use unit_index::{Index, RouteEntry, RouteKind};
+use unit_pubsub::SharedPubSub;
 use unit_utils::{gen_uuid, Result};
 
-use crate::config::CONFIG;
+use crate::{
+    config::CONFIG,
+    metrics::DEPLOYS,
+    validate::{validate_app_code, validate_app_name},
+};
 
 use self::rpc_admin::admin_server::Admin;
 pub use self::rpc_admin::admin_server::AdminServer;
 
 pub struct AdminService {
     index: Mutex<Index>,
+    pubsub: SharedPubSub,
 }
 
 impl AdminService {
-    pub fn new(index: Index) -> Self {
+    pub fn new(index: Index, pubsub: SharedPubSub) -> Self {
         Self {
             index: Mutex::new(index),
+            pubsub,
         }
     }
 }
@@ -36,6 +43,20 @@ fn write_code(id: String, code: &[u8]) -> Result<String> {
     Ok(name)
 }
 
+fn route_kind_from_rpc(kind: rpc_admin::RouteKind) -> RouteKind {
+    match kind {
+        rpc_admin::RouteKind::Alias => RouteKind::Alias,
+        rpc_admin::RouteKind::Host => RouteKind::Host,
+    }
+}
+
+fn route_kind_to_rpc(kind: RouteKind) -> rpc_admin::RouteKind {
+    match kind {
+        RouteKind::Alias => rpc_admin::RouteKind::Alias,
+        RouteKind::Host => rpc_admin::RouteKind::Host,
+    }
+}
+
 #[tonic::async_trait]
 impl Admin for AdminService {
     async fn update_app(
@@ -50,6 +71,34 @@ impl Admin for AdminService {
             ));
         };
 
+        if let Err(e) = validate_app_name(&header.name) {
+            return Err(Status::invalid_argument(e));
+        }
+
+        let code = request.code.clone();
+        let name = header.name.clone();
+        let validation = tokio::task::spawn_blocking(move || {
+            validate_app_code(&code, &name, CONFIG.deploy_dry_run)
+        })
+        .await;
+
+        match validation {
+            Ok(Ok(())) => {}
+            Ok(Err(errors)) => {
+                DEPLOYS.with_label_values(&[&header.name, "rejected"]).inc();
+                info!(
+                    "rejected app code: {} ({} errors)",
+                    &header.name,
+                    errors.len()
+                );
+                return Err(Status::invalid_argument(format!(
+                    "Invalid app module:\n{}",
+                    errors.join("\n")
+                )));
+            }
+            Err(_) => return Err(Status::internal("Failed to validate code")),
+        }
+
         let id = gen_uuid();
 
         let Ok(name) = write_code(id, &request.code) else {
@@ -69,8 +118,152 @@ impl Admin for AdminService {
             return Err(Status::internal("Failed to update index"));
         };
 
+        DEPLOYS
+            .with_label_values(&[&entry.abi_header.name, "deployed"])
+            .inc();
         info!("updated app code: {}", &entry.abi_header.name);
 
         Ok(Response::new(rpc_admin::UpdateAppResponse {}))
     }
+
+    async fn set_route(
+        &self,
+        request: Request<rpc_admin::SetRouteRequest>,
+    ) -> Result<Response<rpc_admin::SetRouteResponse>, Status> {
+        let request = request.into_inner();
+
+        let Some(route) = request.route else {
+            return Err(Status::invalid_argument("route is empty"));
+        };
+
+        if route.name.is_empty() {
+            return Err(Status::invalid_argument("route name is empty"));
+        }
+
+        let Ok(mut index) = self.index.lock() else {
+            return Err(Status::internal("Failed to lock index"));
+        };
+
+        if !index
+            .entries()
+            .iter()
+            .any(|e| e.abi_header.name == route.app)
+        {
+            return Err(Status::not_found("App not found"));
+        }
+
+        let entry = RouteEntry {
+            kind: route_kind_from_rpc(route.kind()),
+            name: route.name,
+            app_name: route.app,
+        };
+
+        if let Err(e) = index.add_or_update_route(entry.clone()) {
+            return Err(Status::failed_precondition(e.to_string()));
+        };
+
+        info!(
+            "updated route: {:?} {} -> {}",
+            entry.kind, &entry.name, &entry.app_name
+        );
+
+        Ok(Response::new(rpc_admin::SetRouteResponse {}))
+    }
+
+    async fn delete_route(
+        &self,
+        request: Request<rpc_admin::DeleteRouteRequest>,
+    ) -> Result<Response<rpc_admin::DeleteRouteResponse>, Status> {
+        let request = request.into_inner();
+        let kind = route_kind_from_rpc(request.kind());
+
+        let Ok(mut index) = self.index.lock() else {
+            return Err(Status::internal("Failed to lock index"));
+        };
+
+        if index.find_route(kind, &request.name).is_none() {
+            return Err(Status::not_found("Route not found"));
+        }
+
+        let Ok(_) = index.remove_route(kind, &request.name) else {
+            return Err(Status::internal("Failed to update index"));
+        };
+
+        info!("deleted route: {:?} {}", kind, &request.name);
+
+        Ok(Response::new(rpc_admin::DeleteRouteResponse {}))
+    }
+
+    async fn list_routes(
+        &self,
+        _request: Request<rpc_admin::ListRoutesRequest>,
+    ) -> Result<Response<rpc_admin::ListRoutesResponse>, Status> {
+        let Ok(index) = self.index.lock() else {
+            return Err(Status::internal("Failed to lock index"));
+        };
+
+        let routes = index
+            .routes()
+            .iter()
+            .map(|route| rpc_admin::Route {
+                kind: route_kind_to_rpc(route.kind) as i32,
+                name: route.name.clone(),
+                app: route.app_name.clone(),
+            })
+            .collect();
+
+        Ok(Response::new(rpc_admin::ListRoutesResponse { routes }))
+    }
+
+    async fn list_nodes(
+        &self,
+        _request: Request<rpc_admin::ListNodesRequest>,
+    ) -> Result<Response<rpc_admin::ListNodesResponse>, Status> {
+        let Ok(nodes) = self.pubsub.list_nodes().await else {
+            return Err(Status::internal("Failed to read node registry"));
+        };
+
+        let nodes = nodes
+            .into_iter()
+            .map(|node| rpc_admin::Node {
+                id: node.id,
+                address: node.address,
+                version: node.version,
+                started_at: node.started_at,
+                last_heartbeat: node.last_heartbeat,
+                draining: node.draining,
+                connections: node.connections,
+            })
+            .collect();
+
+        Ok(Response::new(rpc_admin::ListNodesResponse { nodes }))
+    }
+
+    async fn list_connections(
+        &self,
+        request: Request<rpc_admin::ListConnectionsRequest>,
+    ) -> Result<Response<rpc_admin::ListConnectionsResponse>, Status> {
+        let request = request.into_inner();
+
+        let Ok(records) = self.pubsub.list_presence(&request.app).await else {
+            return Err(Status::internal("Failed to read presence"));
+        };
+
+        let connections = records
+            .into_iter()
+            .map(|record| rpc_admin::Connection {
+                connection_id: record.connection_id,
+                app: record.app_name,
+                node_id: record.node_id,
+                user_id: record.user_id.unwrap_or_default(),
+                room: record.room.unwrap_or_default(),
+                metadata: record.metadata.unwrap_or_default(),
+                joined_at: record.joined_at,
+            })
+            .collect();
+
+        Ok(Response::new(rpc_admin::ListConnectionsResponse {
+            connections,
+        }))
+    }
 }
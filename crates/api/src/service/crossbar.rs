@@ -2,39 +2,237 @@ mod rpc_crossbar {
     tonic::include_proto!("unit.crossbar");
 }
 
-use futures::StreamExt;
-use tonic::{Request, Response, Status};
-use unit_crossbar::{encode_crossbar_message, CrossbarMessage, CROSSBAR_TOPIC};
-use unit_pubsub::PubSub;
-use unit_utils::Result;
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{future::join_all, stream, Stream, StreamExt};
+use log::warn;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{SpanKind, Status as SpanStatus, TraceContextExt},
+    Context, KeyValue,
+};
+use tokio::sync::{broadcast::error::RecvError, oneshot, OnceCell};
+use tonic::{
+    metadata::{KeyRef, MetadataMap},
+    Request, Response, Status,
+};
+use unit_crossbar::{
+    all_apps_channel_prefix, decode_crossbar_message, decode_crossbar_reply,
+    encode_crossbar_message, encode_crossbar_request, is_message_channel, reply_channel,
+    CrossbarContent, CrossbarMessage, CrossbarReplyResult, CrossbarRequest, CrossbarTarget,
+    CROSSBAR_REQUEST_CHANNEL, CROSSBAR_TOPIC,
+};
+use unit_pubsub::{PubSubHealth, PubSubMessage, PublishOutcome, SharedPubSub};
+use unit_telemetry::{inject_context, start_span};
+use unit_utils::{gen_uuid, Result};
+
+use crate::{
+    config::CONFIG,
+    metrics::{
+        result_label, ACTIVE_SUBSCRIBERS, PUBLISH_FAILURES, PUSHED_MESSAGES, PUSH_REQUESTS,
+        PUSH_SECONDS,
+    },
+};
 
 use self::rpc_crossbar::crossbar_server::Crossbar;
 pub use self::rpc_crossbar::crossbar_server::CrossbarServer;
 
+type PendingReplies = Arc<Mutex<HashMap<String, oneshot::Sender<CrossbarReplyResult>>>>;
+
 pub struct CrossbarService {
-    pubsub: PubSub,
+    pubsub: SharedPubSub,
+    // pub/sub subscriptions are only made once the first backend subscribes
+    subscribed: OnceCell<()>,
+    /// Replies to this instance's guest requests arrive on its own channel.
+    instance_id: String,
+    pending_replies: PendingReplies,
+    listening_for_replies: OnceCell<()>,
 }
 
 impl CrossbarService {
-    pub fn new(pubsub: PubSub) -> Self {
-        Self { pubsub }
+    pub fn new(pubsub: SharedPubSub) -> Self {
+        Self {
+            pubsub,
+            subscribed: OnceCell::new(),
+            instance_id: gen_uuid(),
+            pending_replies: Arc::new(Mutex::new(HashMap::new())),
+            listening_for_replies: OnceCell::new(),
+        }
     }
 }
 
-#[tonic::async_trait]
-impl Crossbar for CrossbarService {
-    async fn push(
+type CrossbarEventStream =
+    Pin<Box<dyn Stream<Item = Result<rpc_crossbar::CrossbarEvent, Status>> + Send>>;
+
+struct SubscriberGuard;
+
+impl SubscriberGuard {
+    fn new() -> Self {
+        ACTIVE_SUBSCRIBERS.inc();
+        Self
+    }
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        ACTIVE_SUBSCRIBERS.dec();
+    }
+}
+
+fn subscribe_filter_matches(
+    filter: &rpc_crossbar::SubscribeRequest,
+    message: &CrossbarMessage,
+) -> bool {
+    let topic_matches = filter.topics.is_empty() || filter.topics.contains(&message.topic);
+    let app_matches = filter.apps.is_empty()
+        || message
+            .target
+            .app_name
+            .as_ref()
+            .map_or(false, |app| filter.apps.contains(app));
+
+    topic_matches && app_matches
+}
+
+fn crossbar_event(message: CrossbarMessage) -> rpc_crossbar::CrossbarEvent {
+    let content = match message.content {
+        CrossbarContent::Binary(bytes) => {
+            rpc_crossbar::crossbar_event::Message::Binary(rpc_crossbar::PushBinary {
+                message: bytes,
+            })
+        }
+        CrossbarContent::Text(text) => {
+            rpc_crossbar::crossbar_event::Message::Text(rpc_crossbar::PushText { message: text })
+        }
+    };
+
+    rpc_crossbar::CrossbarEvent {
+        topic: message.topic,
+        app: message.target.app_name.unwrap_or_default(),
+        message: Some(content),
+        message_id: message.message_id,
+        timestamp_ms: message.timestamp_ms,
+        publisher: message.publisher.unwrap_or_default(),
+        content_type: message.content_type.unwrap_or_default(),
+        headers: message.headers.into_iter().collect(),
+    }
+}
+
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl<'a> Extractor for MetadataExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .map(|key| match key {
+                KeyRef::Ascii(key) => key.as_str(),
+                KeyRef::Binary(key) => key.as_str(),
+            })
+            .collect()
+    }
+}
+
+/// Continues the caller's trace when the request carries a `traceparent`.
+fn remote_context(metadata: &MetadataMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&MetadataExtractor(metadata)))
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn crossbar_target(target: Option<rpc_crossbar::PushTarget>) -> CrossbarTarget {
+    let Some(target) = target else {
+        return CrossbarTarget::default();
+    };
+
+    CrossbarTarget {
+        app_name: non_empty(target.app),
+        connection_id: non_empty(target.connection_id),
+        user_id: non_empty(target.user_id),
+    }
+}
+
+/// A push that was validated and encoded, ready to go out.
+struct PreparedPush {
+    message_id: String,
+    channel: String,
+    // set for durable topics
+    stream: Option<String>,
+    bytes: Vec<u8>,
+    idempotency_key: Option<String>,
+    cx: Context,
+}
+
+enum Prepared {
+    Push(PreparedPush),
+    /// The idempotency key was used before, by the message with this id.
+    Duplicate(String),
+}
+
+/// Held by an idempotency key while its push is being sent; the message id replaces it once
+/// the send went through. Short-lived, so a crash mid-send doesn't block the key for long.
+static PENDING_CLAIM: &str = "pending";
+const PENDING_CLAIM_TTL_MS: u64 = 30_000;
+
+struct Published {
+    message_id: String,
+    duplicate: bool,
+    /// Buffered until the pub/sub backend is reachable again.
+    queued: bool,
+}
+
+fn idempotency_claim_key(idempotency_key: &str) -> String {
+    format!("unit:push:{}", idempotency_key)
+}
+
+impl CrossbarService {
+    async fn publish(
         &self,
-        request: Request<rpc_crossbar::PushRequest>,
-    ) -> Result<Response<rpc_crossbar::PushResponse>, Status> {
-        let request: rpc_crossbar::PushRequest = request.into_inner();
+        request: rpc_crossbar::PushRequest,
+        parent: &Context,
+    ) -> Result<Published, Status> {
+        match self.prepare(request, parent).await? {
+            Prepared::Push(push) => self.send(push).await,
+            Prepared::Duplicate(message_id) => Ok(Published {
+                message_id,
+                duplicate: true,
+                queued: false,
+            }),
+        }
+    }
 
-        if request.message.is_none() {
+    async fn prepare(
+        &self,
+        request: rpc_crossbar::PushRequest,
+        parent: &Context,
+    ) -> Result<Prepared, Status> {
+        let Some(message) = request.message else {
             return Err(Status::invalid_argument("message is empty"));
-        }
-        let message = request.message.unwrap();
+        };
         let topic = request.topic;
 
+        let cx = start_span(
+            "crossbar.publish",
+            SpanKind::Producer,
+            parent,
+            vec![KeyValue::new("crossbar.topic", topic.clone())],
+        );
+
         let message = match message {
             rpc_crossbar::push_request::Message::Binary(bytes) => {
                 CrossbarMessage::binary(topic, bytes.message)
@@ -43,51 +241,453 @@ impl Crossbar for CrossbarService {
                 CrossbarMessage::text(topic, text.message)
             }
         };
+        let mut headers: Vec<(String, String)> = request.headers.into_iter().collect();
+        headers.sort();
+
+        let message = CrossbarMessage {
+            trace_context: inject_context(&cx),
+            target: crossbar_target(request.target),
+            publisher: Some("api".to_owned()),
+            content_type: non_empty(request.content_type),
+            headers,
+            ttl_ms: (request.ttl_ms > 0).then_some(request.ttl_ms),
+            ..message
+        };
+        let message_id = message.message_id.clone();
 
-        let Ok(message) = encode_crossbar_message(message) else {
+        let durable = CONFIG.durable.is_durable(&message.topic);
+        let channel = message.channel();
+        let stream = durable.then(|| message.stream());
+        let Ok(bytes) = encode_crossbar_message(message) else {
             return Err(Status::internal("Failed to encode message"));
         };
 
-        let Ok(_) = self.pubsub.publish(CROSSBAR_TOPIC, message).await else {
-            return Err(Status::internal("Failed to publish message"));
+        let idempotency_key =
+            non_empty(request.idempotency_key).map(|key| idempotency_claim_key(&key));
+        if let Some(key) = idempotency_key.as_ref() {
+            let claimed = self
+                .pubsub
+                .claim_or_get(key, PENDING_CLAIM, PENDING_CLAIM_TTL_MS)
+                .await;
+
+            // until the first attempt settles it's unknown whether it will be delivered
+            match claimed {
+                Ok(None) => {}
+                Ok(Some(earlier)) if earlier == PENDING_CLAIM => {
+                    return Err(Status::unavailable(
+                        "A push with the same idempotency key is in progress",
+                    ))
+                }
+                Ok(Some(earlier_id)) => return Ok(Prepared::Duplicate(earlier_id)),
+                Err(_) => return Err(self.publish_error("Failed to check the idempotency key")),
+            }
+        }
+
+        Ok(Prepared::Push(PreparedPush {
+            message_id,
+            channel,
+            stream,
+            bytes,
+            idempotency_key,
+            cx,
+        }))
+    }
+
+    async fn send(&self, push: PreparedPush) -> Result<Published, Status> {
+        let queued = match push.stream.as_ref() {
+            // durable topics are also published live for Subscribe streams; nodes read them
+            // from the stream and ignore the channel copy, so once the append went through
+            // the push happened and the live copy is best effort
+            Some(stream) => {
+                let appended = self
+                    .pubsub
+                    .append(stream, push.bytes.clone(), CONFIG.durable.max_len)
+                    .await;
+                if appended.is_err() {
+                    return Err(self.send_failed(push.idempotency_key, &push.cx).await);
+                }
+
+                if let Err(e) = self.pubsub.publish(&push.channel, push.bytes).await {
+                    PUBLISH_FAILURES.inc();
+                    warn!(
+                        "failed to publish durable message {} live {:?}",
+                        push.message_id, e
+                    );
+                }
+
+                false
+            }
+            None => match self.pubsub.publish(&push.channel, push.bytes).await {
+                Ok(outcome) => outcome == PublishOutcome::Queued,
+                Err(_) => return Err(self.send_failed(push.idempotency_key, &push.cx).await),
+            },
         };
 
-        Ok(Response::new(rpc_crossbar::PushResponse {}))
+        PUSHED_MESSAGES.inc();
+
+        if let Some(key) = push.idempotency_key.as_ref() {
+            let settled = self
+                .pubsub
+                .set_claim(key, &push.message_id, CONFIG.idempotency_ttl_ms)
+                .await;
+            if let Err(e) = settled {
+                warn!(
+                    "failed to record idempotency key of message {} {:?}",
+                    push.message_id, e
+                );
+            }
+        }
+
+        Ok(Published {
+            message_id: push.message_id,
+            duplicate: false,
+            queued,
+        })
+    }
+
+    async fn send_failed(&self, idempotency_key: Option<String>, cx: &Context) -> Status {
+        // so a retry with the same key isn't taken for a duplicate
+        if let Some(key) = idempotency_key {
+            let _ = self.pubsub.unclaim(&key).await;
+        }
+
+        PUBLISH_FAILURES.inc();
+        cx.span()
+            .set_status(SpanStatus::error("failed to publish message"));
+        self.publish_error("Failed to publish message")
+    }
+
+    /// Unavailable while the backend is reconnecting, so clients know to retry.
+    fn publish_error(&self, message: &str) -> Status {
+        match self.pubsub.health() {
+            PubSubHealth::Connected => Status::internal(message),
+            PubSubHealth::Reconnecting => {
+                Status::unavailable(format!("{}: the pub/sub backend is reconnecting", message))
+            }
+        }
+    }
+
+    async fn ensure_subscribed(&self) -> Result<(), Status> {
+        let result = self
+            .subscribed
+            .get_or_try_init(|| async {
+                self.pubsub.subscribe(CROSSBAR_TOPIC).await?;
+                self.pubsub
+                    .subscribe_prefix(all_apps_channel_prefix())
+                    .await?;
+
+                Ok::<_, unit_utils::err::anyhow::Error>(())
+            })
+            .await;
+
+        if result.is_err() {
+            return Err(Status::unavailable("Failed to subscribe to the crossbar"));
+        }
+
+        Ok(())
+    }
+
+    async fn ensure_listening_for_replies(&self) -> Result<(), Status> {
+        let result = self
+            .listening_for_replies
+            .get_or_try_init(|| async {
+                let channel = reply_channel(&self.instance_id);
+                let mut rx = self.pubsub.on_message();
+                let pending_replies = self.pending_replies.clone();
+                let task_channel = channel.clone();
+
+                tokio::spawn(async move {
+                    loop {
+                        let message = match rx.recv().await {
+                            Ok(message) => message,
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => break,
+                        };
+
+                        if message.channel != task_channel {
+                            continue;
+                        }
+
+                        let Ok(reply) = decode_crossbar_reply(&message.payload) else {
+                            continue;
+                        };
+
+                        // late replies find no one waiting
+                        let Some(tx) = pending_replies.lock().unwrap().remove(&reply.request_id)
+                        else {
+                            continue;
+                        };
+                        let _ = tx.send(reply.result);
+                    }
+                });
+
+                self.pubsub.subscribe(&channel).await?;
+
+                Ok::<_, unit_utils::err::anyhow::Error>(())
+            })
+            .await;
+
+        if result.is_err() {
+            return Err(Status::unavailable("Failed to subscribe to replies"));
+        }
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Crossbar for CrossbarService {
+    type SubscribeStream = CrossbarEventStream;
+
+    async fn push(
+        &self,
+        request: Request<rpc_crossbar::PushRequest>,
+    ) -> Result<Response<rpc_crossbar::PushResponse>, Status> {
+        let _timer = PUSH_SECONDS.with_label_values(&["push"]).start_timer();
+        let cx = start_span(
+            "crossbar.push",
+            SpanKind::Server,
+            &remote_context(request.metadata()),
+            vec![],
+        );
+
+        let result = self.publish(request.into_inner(), &cx).await;
+        PUSH_REQUESTS
+            .with_label_values(&["push", result_label(&result)])
+            .inc();
+        let published = result?;
+
+        Ok(Response::new(rpc_crossbar::PushResponse {
+            message_id: published.message_id,
+            duplicate: published.duplicate,
+            queued: published.queued,
+        }))
     }
 
     async fn push_stream(
         &self,
         request: Request<tonic::Streaming<rpc_crossbar::PushRequest>>,
     ) -> Result<Response<rpc_crossbar::PushResponse>, Status> {
+        let _timer = PUSH_SECONDS
+            .with_label_values(&["push_stream"])
+            .start_timer();
+        let cx = start_span(
+            "crossbar.push_stream",
+            SpanKind::Server,
+            &remote_context(request.metadata()),
+            vec![],
+        );
         let mut stream = request.into_inner();
 
-        while let Some(request) = stream.next().await {
-            let request = request?;
-            if request.message.is_none() {
-                return Err(Status::invalid_argument("message is empty"));
+        let result = async {
+            while let Some(request) = stream.next().await {
+                self.publish(request?, &cx).await?;
             }
 
-            let message = request.message.unwrap();
-            let topic = request.topic;
+            Ok::<_, Status>(())
+        }
+        .await;
 
-            let message = match message {
-                rpc_crossbar::push_request::Message::Binary(bytes) => {
-                    CrossbarMessage::binary(topic, bytes.message)
+        PUSH_REQUESTS
+            .with_label_values(&["push_stream", result_label(&result)])
+            .inc();
+        result?;
+
+        Ok(Response::new(rpc_crossbar::PushResponse::default()))
+    }
+
+    async fn push_batch(
+        &self,
+        request: Request<rpc_crossbar::PushBatchRequest>,
+    ) -> Result<Response<rpc_crossbar::PushBatchResponse>, Status> {
+        let _timer = PUSH_SECONDS
+            .with_label_values(&["push_batch"])
+            .start_timer();
+        let cx = start_span(
+            "crossbar.push_batch",
+            SpanKind::Server,
+            &remote_context(request.metadata()),
+            vec![],
+        );
+
+        let messages = request.into_inner().messages;
+        if messages.len() > CONFIG.push_batch_limit {
+            PUSH_REQUESTS
+                .with_label_values(&["push_batch", "error"])
+                .inc();
+            return Err(Status::invalid_argument(format!(
+                "A batch can have at most {} messages",
+                CONFIG.push_batch_limit
+            )));
+        }
+
+        // everything is prepared before anything is sent, so the sends below go out back to
+        // back, in request order, and the backend can pipeline them
+        let prepared = join_all(messages.into_iter().map(|m| self.prepare(m, &cx))).await;
+
+        let mut results: Vec<Option<Result<Published, Status>>> = vec![];
+        let mut pushes = vec![];
+        for (index, prepared) in prepared.into_iter().enumerate() {
+            match prepared {
+                Ok(Prepared::Push(push)) => {
+                    results.push(None);
+                    pushes.push((index, push));
                 }
-                rpc_crossbar::push_request::Message::Text(text) => {
-                    CrossbarMessage::text(topic, text.message)
+                Ok(Prepared::Duplicate(message_id)) => results.push(Some(Ok(Published {
+                    message_id,
+                    duplicate: true,
+                    queued: false,
+                }))),
+                Err(e) => results.push(Some(Err(e))),
+            }
+        }
+
+        let sent = join_all(
+            pushes
+                .into_iter()
+                .map(|(index, push)| async move { (index, self.send(push).await) }),
+        )
+        .await;
+        for (index, result) in sent {
+            results[index] = Some(result);
+        }
+
+        let results: Vec<rpc_crossbar::PushResult> = results
+            .into_iter()
+            .flatten()
+            .map(|result| match result {
+                Ok(published) => rpc_crossbar::PushResult {
+                    message_id: published.message_id,
+                    duplicate: published.duplicate,
+                    queued: published.queued,
+                    ..Default::default()
+                },
+                Err(status) => rpc_crossbar::PushResult {
+                    code: status.code() as i32,
+                    error: status.message().to_owned(),
+                    ..Default::default()
+                },
+            })
+            .collect();
+
+        let outcome = match results.iter().all(|result| result.code == 0) {
+            true => "ok",
+            false => "partial",
+        };
+        PUSH_REQUESTS
+            .with_label_values(&["push_batch", outcome])
+            .inc();
+
+        Ok(Response::new(rpc_crossbar::PushBatchResponse { results }))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<rpc_crossbar::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        self.ensure_subscribed().await?;
+
+        let filter = request.into_inner();
+        let rx = self.pubsub.on_message();
+        let guard = SubscriberGuard::new();
+
+        let events = stream::unfold((rx, filter, guard), |(mut rx, filter, guard)| async move {
+            loop {
+                let bytes = match rx.recv().await {
+                    Ok(PubSubMessage { channel, payload }) if is_message_channel(&channel) => {
+                        payload
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                };
+
+                let Ok(message) = decode_crossbar_message(bytes) else {
+                    continue;
+                };
+
+                if message.is_expired() || !subscribe_filter_matches(&filter, &message) {
+                    continue;
                 }
-            };
 
-            let Ok(message) = encode_crossbar_message(message) else {
-                return Err(Status::internal("Failed to encode message"));
-            };
+                return Some((
+                    Ok::<_, Status>(crossbar_event(message)),
+                    (rx, filter, guard),
+                ));
+            }
+        });
+
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn request(
+        &self,
+        request: Request<rpc_crossbar::GuestRequest>,
+    ) -> Result<Response<rpc_crossbar::GuestReply>, Status> {
+        self.ensure_listening_for_replies().await?;
 
-            let Ok(_) = self.pubsub.publish(CROSSBAR_TOPIC, message).await else {
-                return Err(Status::internal("Failed to publish message"));
-            };
+        let request = request.into_inner();
+        if request.name.is_empty() {
+            return Err(Status::invalid_argument("name is empty"));
         }
 
-        Ok(Response::new(rpc_crossbar::PushResponse {}))
+        let target = crossbar_target(request.target);
+        if target.app_name.is_none() && target.connection_id.is_none() && target.user_id.is_none() {
+            return Err(Status::invalid_argument("target is required"));
+        }
+
+        let timeout_ms = match request.timeout_ms {
+            0 => CONFIG.request_timeout_ms,
+            timeout_ms => timeout_ms.min(CONFIG.max_request_timeout_ms),
+        };
+
+        let request_id = gen_uuid();
+        let (tx, rx) = oneshot::channel();
+        self.pending_replies
+            .lock()
+            .unwrap()
+            .insert(request_id.clone(), tx);
+
+        let guest_request = CrossbarRequest {
+            request_id: request_id.clone(),
+            name: request.name.clone(),
+            payload: request.payload,
+            target,
+            reply_channel: reply_channel(&self.instance_id),
+            timeout_ms,
+        };
+
+        let published = match encode_crossbar_request(&guest_request) {
+            Ok(bytes) => self
+                .pubsub
+                .publish(CROSSBAR_REQUEST_CHANNEL, bytes)
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if published.is_err() {
+            self.pending_replies.lock().unwrap().remove(&request_id);
+            PUBLISH_FAILURES.inc();
+            return Err(self.publish_error("Failed to publish request"));
+        }
+
+        let result = tokio::time::timeout(Duration::from_millis(timeout_ms), rx).await;
+        self.pending_replies.lock().unwrap().remove(&request_id);
+
+        match result {
+            Ok(Ok(CrossbarReplyResult::Ok(payload))) => {
+                Ok(Response::new(rpc_crossbar::GuestReply { payload }))
+            }
+            Ok(Ok(CrossbarReplyResult::NoHandler)) => Err(Status::unimplemented(format!(
+                "App has no handler for request {}",
+                request.name
+            ))),
+            Ok(Ok(CrossbarReplyResult::Failed(e))) => {
+                Err(Status::internal(format!("Request handler failed: {}", e)))
+            }
+            Ok(Err(_)) | Err(_) => Err(Status::deadline_exceeded(
+                "No instance replied before the timeout",
+            )),
+        }
     }
 }
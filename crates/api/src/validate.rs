@@ -0,0 +1,184 @@
+use unit_abi::surface::{
+    find_export, find_host_function, AbiFunction, AbiType, HOST_MEMORY, HOST_MODULE,
+    REQUIRED_EXPORTS, WASI_MODULES,
+};
+use wasmer::{
+    ExternType, Function, FunctionType, Imports, Instance, Memory, Module, RuntimeError, Store,
+    Type, Value,
+};
+use wasmer_wasix::WasiEnv;
+
+fn abi_type_to_wasm(ty: &AbiType) -> Type {
+    match ty {
+        AbiType::I32 => Type::I32,
+        AbiType::I64 => Type::I64,
+    }
+}
+
+fn signature_matches(expected: &AbiFunction, actual: &FunctionType) -> bool {
+    let params: Vec<Type> = expected.params.iter().map(abi_type_to_wasm).collect();
+    let results: Vec<Type> = expected.results.iter().map(abi_type_to_wasm).collect();
+
+    actual.params() == params.as_slice() && actual.results() == results.as_slice()
+}
+
+fn default_value(ty: &Type) -> Value {
+    match ty {
+        Type::I64 => Value::I64(0),
+        Type::F32 => Value::F32(0.0),
+        Type::F64 => Value::F64(0.0),
+        _ => Value::I32(0),
+    }
+}
+
+fn check_imports(module: &Module, errors: &mut Vec<String>) {
+    for import in module.imports() {
+        let (namespace, name) = (import.module(), import.name());
+
+        if WASI_MODULES.contains(&namespace) {
+            continue;
+        }
+
+        if namespace != HOST_MODULE {
+            errors.push(format!("import {namespace}.{name}: unknown import module"));
+            continue;
+        }
+
+        match import.ty() {
+            ExternType::Memory(_) if name == HOST_MEMORY => {}
+            ExternType::Function(ty) => match find_host_function(name) {
+                Some(host_fn) if signature_matches(host_fn, ty) => {}
+                Some(_) => errors.push(format!(
+                    "import {namespace}.{name}: signature {ty} does not match the host function"
+                )),
+                None => errors.push(format!("import {namespace}.{name}: unknown host function")),
+            },
+            other => errors.push(format!(
+                "import {namespace}.{name}: unsupported import type {other:?}"
+            )),
+        }
+    }
+
+    if module.imports().memories().next().is_none() {
+        errors.push(format!(
+            "import {HOST_MODULE}.{HOST_MEMORY}: module must import its memory"
+        ));
+    }
+}
+
+fn check_exports(module: &Module, errors: &mut Vec<String>) {
+    for required in REQUIRED_EXPORTS {
+        let exported = module
+            .exports()
+            .functions()
+            .any(|e| e.name() == required.name);
+        if !exported {
+            errors.push(format!("export {}: missing required export", required.name));
+        }
+    }
+
+    for export in module.exports().functions() {
+        let Some(expected) = find_export(export.name()) else {
+            continue;
+        };
+
+        if !signature_matches(expected, export.ty()) {
+            errors.push(format!(
+                "export {}: signature {} does not match the unit ABI",
+                export.name(),
+                export.ty()
+            ));
+        }
+    }
+}
+
+fn dry_run(store: &mut Store, module: &Module, name: &str) -> Result<(), String> {
+    let Some(memory_ty) = module.imports().memories().next().map(|a| *a.ty()) else {
+        return Err("module does not import a memory".to_owned());
+    };
+    let memory = Memory::new(store, memory_ty).map_err(|e| e.to_string())?;
+
+    let mut wasi_env = WasiEnv::builder(name)
+        .finalize(store)
+        .map_err(|e| e.to_string())?;
+
+    let mut import_object = wasi_env
+        .import_object_for_all_wasi_versions(store, module)
+        .map_err(|e| e.to_string())?;
+    import_object.define(HOST_MODULE, HOST_MEMORY, memory.clone());
+
+    if let Some(thread_spawn) = import_object.get_export("wasi_snapshot_preview1", "thread-spawn") {
+        import_object.define("wasi", "thread-spawn", thread_spawn);
+    }
+
+    let mut host_imports = Imports::new();
+    for import in module.imports().functions() {
+        if import.module() != HOST_MODULE {
+            continue;
+        }
+
+        let ty = import.ty().clone();
+        let results: Vec<Value> = ty.results().iter().map(default_value).collect();
+        let stub = Function::new(store, ty, move |_| Ok::<_, RuntimeError>(results.clone()));
+        host_imports.define(HOST_MODULE, import.name(), stub);
+    }
+    import_object.extend(host_imports.into_iter());
+
+    let instance = Instance::new(store, module, &import_object).map_err(|e| e.to_string())?;
+
+    wasi_env
+        .initialize_with_memory(store, instance, Some(memory), true)
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+const MAX_APP_NAME_LEN: usize = 64;
+
+/// App names end up in URLs, pubsub keys and metric labels, so they are limited to
+/// ASCII letters, digits, `-` and `_`.
+pub fn validate_app_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_APP_NAME_LEN {
+        return Err(format!(
+            "app name must be 1 to {MAX_APP_NAME_LEN} characters long"
+        ));
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "app name {name:?} may only contain ASCII letters, digits, '-' and '_'"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Compiles the module and checks it against the unit ABI surface. Returns every problem found.
+pub fn validate_app_code(code: &[u8], name: &str, instantiate: bool) -> Result<(), Vec<String>> {
+    let mut store = Store::default();
+
+    let module = match Module::new(&store, code) {
+        Ok(module) => module,
+        Err(e) => return Err(vec![format!("compile: {e}")]),
+    };
+
+    let mut errors = vec![];
+
+    check_imports(&module, &mut errors);
+    check_exports(&module, &mut errors);
+
+    if errors.is_empty() && instantiate {
+        if let Err(e) = dry_run(&mut store, &module, name) {
+            errors.push(format!("instantiate: {e}"));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use unit_utils::Result;
+
+use crate::services::Admin;
+
+fn or_dash(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
+
+pub async fn run_connections(app: String) -> Result<()> {
+    let mut admin = Admin::new().await?;
+    let connections = admin.list_connections(app.clone()).await?;
+
+    if connections.is_empty() {
+        println!("No connections to {}", app);
+        return Ok(());
+    }
+
+    for connection in connections {
+        println!(
+            "{}\tnode {}\tuser {}\troom {}\t{}",
+            connection.connection_id,
+            connection.node_id,
+            or_dash(&connection.user_id),
+            or_dash(&connection.room),
+            or_dash(&connection.metadata)
+        );
+    }
+
+    Ok(())
+}
@@ -1,9 +1,12 @@
 use clap::{Parser, Subcommand};
 use unit_utils::{err::bail, Result};
 
-use self::deploy::Deploy;
+use self::{deploy::Deploy, routes::Routes};
 
+mod connections;
 mod deploy;
+mod nodes;
+mod routes;
 
 #[derive(Parser)]
 #[command(author, version)]
@@ -17,6 +20,15 @@ pub struct Cli {
 pub enum Commands {
     /// Deploy code to unit
     Deploy(Deploy),
+    /// Manage app routing rules (aliases and hosts)
+    Routes(Routes),
+    /// Show the nodes in the cluster and their connections
+    Nodes,
+    /// Show who is connected to an app across the cluster
+    Connections {
+        /// App name
+        app: String,
+    },
 }
 
 pub async fn start_cli() -> Result<()> {
@@ -24,6 +36,9 @@ pub async fn start_cli() -> Result<()> {
 
     match cli.command {
         Some(Commands::Deploy(deploy)) => deploy::run_deploy(deploy).await?,
+        Some(Commands::Routes(routes)) => routes::run_routes(routes).await?,
+        Some(Commands::Nodes) => nodes::run_nodes().await?,
+        Some(Commands::Connections { app }) => connections::run_connections(app).await?,
         None => bail!("No command provided"),
     };
 
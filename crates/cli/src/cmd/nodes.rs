@@ -0,0 +1,43 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use unit_utils::Result;
+
+use crate::services::Admin;
+
+pub async fn run_nodes() -> Result<()> {
+    let mut admin = Admin::new().await?;
+    let nodes = admin.list_nodes().await?;
+
+    if nodes.is_empty() {
+        println!("No live nodes");
+        return Ok(());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    for node in nodes {
+        let status = if node.draining { "draining" } else { "ready" };
+        let total: u64 = node.connections.values().sum();
+
+        println!(
+            "{}\t{}\tv{}\t{}\t{} connections\tseen {}s ago",
+            node.id,
+            node.address,
+            node.version,
+            status,
+            total,
+            now.saturating_sub(node.last_heartbeat)
+        );
+
+        let mut apps: Vec<_> = node.connections.into_iter().collect();
+        apps.sort();
+        for (app, count) in apps {
+            println!("  {app}\t{count}");
+        }
+    }
+
+    Ok(())
+}
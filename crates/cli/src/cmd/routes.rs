@@ -0,0 +1,76 @@
+use clap::{Args, Subcommand, ValueEnum};
+use unit_utils::Result;
+
+use crate::services::{admin::rpc_admin::RouteKind, Admin};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Kind {
+    /// Alternative name clients can connect with (e.g. /ws/chat-beta)
+    Alias,
+    /// Host header routed to the app
+    Host,
+}
+
+impl Kind {
+    fn to_rpc(self) -> RouteKind {
+        match self {
+            Kind::Alias => RouteKind::Alias,
+            Kind::Host => RouteKind::Host,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct Routes {
+    #[command(subcommand)]
+    command: RoutesCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RoutesCommand {
+    /// List routing rules
+    List,
+    /// Route an alias or a host to an app
+    Set {
+        kind: Kind,
+        /// Alias or host name
+        name: String,
+        /// Target app name
+        app: String,
+    },
+    /// Delete a routing rule
+    Delete {
+        kind: Kind,
+        /// Alias or host name
+        name: String,
+    },
+}
+
+pub async fn run_routes(args: Routes) -> Result<()> {
+    let mut admin = Admin::new().await?;
+
+    match args.command {
+        RoutesCommand::List => {
+            for route in admin.list_routes().await? {
+                let kind = match route.kind() {
+                    RouteKind::Alias => "alias",
+                    RouteKind::Host => "host",
+                };
+
+                println!("{kind}\t{}\t-> {}", route.name, route.app);
+            }
+        }
+        RoutesCommand::Set { kind, name, app } => {
+            admin
+                .set_route(kind.to_rpc(), name.clone(), app.clone())
+                .await?;
+            println!("Routed {name} to {app}");
+        }
+        RoutesCommand::Delete { kind, name } => {
+            admin.delete_route(kind.to_rpc(), name.clone()).await?;
+            println!("Deleted route {name}");
+        }
+    }
+
+    Ok(())
+}
@@ -5,15 +5,18 @@ pub mod rpc_admin {
 use std::str::FromStr;
 
 // use rpc_admin::{a::EchoClient, EchoRequest};
-use rpc_admin::{admin_client::AdminClient, UpdateAppRequest};
+use rpc_admin::{
+    admin_client::AdminClient, Connection, DeleteRouteRequest, ListConnectionsRequest,
+    ListNodesRequest, ListRoutesRequest, Node, Route, RouteKind, SetRouteRequest, UpdateAppRequest,
+};
 use tonic::{
     codegen::InterceptedService,
     metadata::MetadataValue,
     service::Interceptor,
-    transport::{Channel, Endpoint},
-    Request, Status,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+    Code, Request, Status,
 };
-use unit_utils::{env, Result};
+use unit_utils::{env, err::bail, Result};
 
 struct AuthInterceptor;
 
@@ -29,6 +32,34 @@ impl Interceptor for AuthInterceptor {
     }
 }
 
+// UNIT_CLI_CA_CERT: PEM bundle trusted in addition to the system roots
+// UNIT_CLI_TLS_CERT / UNIT_CLI_TLS_KEY: client certificate for APIs that require mutual TLS
+fn tls_config() -> Result<ClientTlsConfig> {
+    let mut tls = ClientTlsConfig::new();
+
+    if let Some(ca_path) = env::optional_str("UNIT_CLI_CA_CERT") {
+        tls = tls.ca_certificate(Certificate::from_pem(std::fs::read(ca_path)?));
+    }
+
+    match (
+        env::optional_str("UNIT_CLI_TLS_CERT"),
+        env::optional_str("UNIT_CLI_TLS_KEY"),
+    ) {
+        (Some(cert_path), Some(key_path)) => {
+            let identity = Identity::from_pem(std::fs::read(cert_path)?, std::fs::read(key_path)?);
+            tls = tls.identity(identity);
+        }
+        (None, None) => {}
+        _ => bail!("UNIT_CLI_TLS_CERT and UNIT_CLI_TLS_KEY must be set together"),
+    }
+
+    if let Some(domain) = env::optional_str("UNIT_CLI_TLS_DOMAIN") {
+        tls = tls.domain_name(domain);
+    }
+
+    Ok(tls)
+}
+
 pub struct Admin {
     client: AdminClient<InterceptedService<Channel, AuthInterceptor>>,
 }
@@ -36,7 +67,12 @@ pub struct Admin {
 impl Admin {
     pub async fn new() -> Result<Admin> {
         let endpoint = env::required_str("UNIT_CLI_API_ENDPOINT");
-        let channel = Endpoint::from_str(&endpoint)?.connect().await?;
+        let mut endpoint = Endpoint::from_str(&endpoint)?;
+        if endpoint.uri().scheme_str() == Some("https") {
+            endpoint = endpoint.tls_config(tls_config()?)?;
+        }
+
+        let channel = endpoint.connect().await?;
 
         let client = AdminClient::with_interceptor(channel, AuthInterceptor);
 
@@ -44,10 +80,67 @@ impl Admin {
     }
 
     pub async fn update_app(&mut self, code: Vec<u8>) -> Result<()> {
-        self.client
+        let result = self
+            .client
             .update_app(Request::new(UpdateAppRequest { code }))
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(status) if status.code() == Code::InvalidArgument => bail!("{}", status.message()),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    pub async fn set_route(&mut self, kind: RouteKind, name: String, app: String) -> Result<()> {
+        let route = Route {
+            kind: kind as i32,
+            name,
+            app,
+        };
+
+        self.client
+            .set_route(Request::new(SetRouteRequest { route: Some(route) }))
             .await?;
 
         Ok(())
     }
+
+    pub async fn delete_route(&mut self, kind: RouteKind, name: String) -> Result<()> {
+        self.client
+            .delete_route(Request::new(DeleteRouteRequest {
+                kind: kind as i32,
+                name,
+            }))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_routes(&mut self) -> Result<Vec<Route>> {
+        let response = self
+            .client
+            .list_routes(Request::new(ListRoutesRequest {}))
+            .await?;
+
+        Ok(response.into_inner().routes)
+    }
+
+    pub async fn list_nodes(&mut self) -> Result<Vec<Node>> {
+        let response = self
+            .client
+            .list_nodes(Request::new(ListNodesRequest {}))
+            .await?;
+
+        Ok(response.into_inner().nodes)
+    }
+
+    pub async fn list_connections(&mut self, app: String) -> Result<Vec<Connection>> {
+        let response = self
+            .client
+            .list_connections(Request::new(ListConnectionsRequest { app }))
+            .await?;
+
+        Ok(response.into_inner().connections)
+    }
 }
@@ -2,20 +2,144 @@ pub mod rpc_crossbar {
     tonic::include_proto!("unit.crossbar");
 }
 
-use std::str::FromStr;
+use std::{
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
 
 // use rpc_admin::{a::EchoClient, EchoRequest};
+use futures::{channel::mpsc, Sink};
+use opentelemetry::{global, propagation::Injector, Context};
 use rpc_crossbar::{crossbar_client::CrossbarClient, push_request};
-pub use rpc_crossbar::{push_request::Message, PushBinary, PushRequest, PushResponse, PushText};
+pub use rpc_crossbar::{
+    crossbar_event, push_request::Message, CrossbarEvent, GuestReply, GuestRequest,
+    PushBatchRequest, PushBatchResponse, PushBinary, PushRequest, PushResponse, PushResult,
+    PushTarget, PushText, SubscribeRequest,
+};
+use serde::Serialize;
+use tokio::task::JoinHandle;
 use tonic::{
     codegen::InterceptedService,
-    metadata::MetadataValue,
+    metadata::{Ascii, MetadataKey, MetadataMap, MetadataValue},
     service::Interceptor,
     transport::{Channel, Endpoint},
-    Request, Status,
+    Code, Request, Response, Status,
+};
+pub use tonic::{
+    transport::{Certificate, ClientTlsConfig, Identity},
+    Streaming,
 };
-use unit_utils::Result;
+use unit_utils::{
+    env,
+    err::{anyhow, bail},
+    gen_uuid, Result,
+};
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl<'a> Injector for MetadataInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        let (Ok(key), Ok(value)) = (
+            key.parse::<MetadataKey<Ascii>>(),
+            value.parse::<MetadataValue<Ascii>>(),
+        ) else {
+            return;
+        };
+
+        self.0.insert(key, value);
+    }
+}
+
+impl PushTarget {
+    /// Every connection of an app; only nodes running the app receive the push.
+    pub fn app(app: impl Into<String>) -> Self {
+        Self {
+            app: app.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A single connection, by the id the node assigned to it.
+    pub fn connection(connection_id: impl Into<String>) -> Self {
+        Self {
+            connection_id: connection_id.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Every connection whose guest bound this user id.
+    pub fn user(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl PushRequest {
+    pub fn text(topic: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+            message: Some(push_request::Message::Text(PushText {
+                message: text.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Binary payloads are delivered byte for byte, whether or not they are valid UTF-8.
+    pub fn binary(topic: impl Into<String>, bytes: Vec<u8>) -> Self {
+        Self {
+            topic: topic.into(),
+            message: Some(push_request::Message::Binary(PushBinary { message: bytes })),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_target(mut self, target: PushTarget) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Copies not delivered within `ttl` are dropped.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl_ms = ttl.as_millis() as u64;
+        self
+    }
+
+    /// A JSON text message, with `application/json` as its content type.
+    pub fn json<T: Serialize>(topic: impl Into<String>, value: &T) -> Result<Self> {
+        let text = serde_json::to_string(value)?;
+        Ok(Self::text(topic, text).with_content_type("application/json"))
+    }
+
+    /// Retried pushes with the same key are only published once.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = key.into();
+        self
+    }
+}
+
+impl PushResult {
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+}
 
+#[derive(Clone)]
 pub struct AuthInterceptor {
     api_key: String,
 }
@@ -34,42 +158,450 @@ impl Interceptor for AuthInterceptor {
 
         req.metadata_mut().insert("authorization", token.clone());
 
+        // lets the API continue the caller's trace (no-op unless a propagator is installed)
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(
+                &Context::current(),
+                &mut MetadataInjector(req.metadata_mut()),
+            )
+        });
+
         Ok(req)
     }
 }
 
+type CrossbarRpc = CrossbarClient<InterceptedService<Channel, AuthInterceptor>>;
+
+/// Calls failing with `Unavailable` are retried, waiting `initial_backoff` and then twice as
+/// long each time, up to `max_backoff`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+pub struct CrossbarBuilder {
+    endpoint: String,
+    api_key: String,
+    tls: Option<ClientTlsConfig>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    idempotency_keys: bool,
+    sink_buffer: usize,
+}
+
+impl CrossbarBuilder {
+    pub fn new(endpoint: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            api_key: api_key.into(),
+            tls: None,
+            connect_timeout: None,
+            timeout: None,
+            retry: RetryPolicy::default(),
+            idempotency_keys: false,
+            sink_buffer: 1024,
+        }
+    }
+
+    /// Reads `UNIT_CROSSBAR_ENDPOINT` and `UNIT_CROSSBAR_API_KEY`, plus the optional
+    /// `UNIT_CROSSBAR_TLS_CA`, `UNIT_CROSSBAR_TLS_CERT`, `UNIT_CROSSBAR_TLS_KEY` and
+    /// `UNIT_CROSSBAR_TIMEOUT_MS`.
+    pub fn from_env() -> Result<Self> {
+        env::load_env();
+
+        let (Some(endpoint), Some(api_key)) = (
+            env::optional_str("UNIT_CROSSBAR_ENDPOINT"),
+            env::optional_str("UNIT_CROSSBAR_API_KEY"),
+        ) else {
+            bail!("UNIT_CROSSBAR_ENDPOINT and UNIT_CROSSBAR_API_KEY must be set");
+        };
+
+        let mut builder = Self::new(endpoint, api_key);
+
+        let ca_path = env::optional_str("UNIT_CROSSBAR_TLS_CA");
+        let cert_path = env::optional_str("UNIT_CROSSBAR_TLS_CERT");
+        let key_path = env::optional_str("UNIT_CROSSBAR_TLS_KEY");
+        if ca_path.is_some() || cert_path.is_some() || key_path.is_some() {
+            let mut tls = ClientTlsConfig::new();
+            if let Some(ca_path) = ca_path {
+                tls = tls.ca_certificate(Certificate::from_pem(std::fs::read(ca_path)?));
+            }
+            match (cert_path, key_path) {
+                (Some(cert_path), Some(key_path)) => {
+                    let cert = std::fs::read(cert_path)?;
+                    let key = std::fs::read(key_path)?;
+                    tls = tls.identity(Identity::from_pem(cert, key));
+                }
+                (None, None) => {}
+                _ => bail!("UNIT_CROSSBAR_TLS_CERT and UNIT_CROSSBAR_TLS_KEY must be set together"),
+            }
+            builder = builder.tls(tls);
+        }
+
+        if let Some(timeout_ms) = env::optional_value::<u64>("UNIT_CROSSBAR_TIMEOUT_MS") {
+            builder = builder.timeout(Duration::from_millis(timeout_ms));
+        }
+
+        Ok(builder)
+    }
+
+    /// A custom CA bundle and/or client certificate (mutual TLS). Without it, `https://`
+    /// endpoints are verified against the system roots.
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Deadline of each call; streaming calls aren't limited.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Gives pushes without an idempotency key a generated one, so they can be retried too.
+    /// Each key costs the API a write to the pub/sub backend and is kept for its idempotency TTL
+    /// (an hour by default), so at high push rates prefer keying the pushes that need it.
+    pub fn idempotency_keys(mut self, enabled: bool) -> Self {
+        self.idempotency_keys = enabled;
+        self
+    }
+
+    /// Messages a `PushSink` holds before `send` waits for the stream to catch up.
+    pub fn sink_buffer(mut self, sink_buffer: usize) -> Self {
+        self.sink_buffer = sink_buffer;
+        self
+    }
+
+    fn endpoint(&self) -> Result<Endpoint> {
+        let mut endpoint = Endpoint::from_str(&self.endpoint)?
+            .http2_keep_alive_interval(Duration::from_secs(30))
+            .keep_alive_while_idle(true);
+
+        match self.tls.clone() {
+            Some(tls) => endpoint = endpoint.tls_config(tls)?,
+            None if endpoint.uri().scheme_str() == Some("https") => {
+                endpoint = endpoint.tls_config(ClientTlsConfig::new())?
+            }
+            None => {}
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+
+        Ok(endpoint)
+    }
+
+    fn with_channel(self, channel: Channel) -> Crossbar {
+        let client = CrossbarClient::with_interceptor(channel, AuthInterceptor::new(self.api_key));
+
+        Crossbar {
+            client,
+            retry: self.retry,
+            idempotency_keys: self.idempotency_keys,
+            timeout: self.timeout,
+            sink_buffer: self.sink_buffer,
+        }
+    }
+
+    /// Connects right away, so a bad endpoint fails here; a dropped connection is
+    /// re-established on the next call either way.
+    pub async fn connect(self) -> Result<Crossbar> {
+        let channel = self.endpoint()?.connect().await?;
+        Ok(self.with_channel(channel))
+    }
+
+    /// Connects on the first call instead.
+    pub fn connect_lazy(self) -> Result<Crossbar> {
+        let channel = self.endpoint()?.connect_lazy();
+        Ok(self.with_channel(channel))
+    }
+}
+
+/// A handle to the crossbar API. Clones share the connection, so one can be handed to every
+/// task that pushes.
+#[derive(Clone)]
 pub struct Crossbar {
-    pub client: CrossbarClient<InterceptedService<Channel, AuthInterceptor>>,
+    pub client: CrossbarRpc,
+    retry: RetryPolicy,
+    idempotency_keys: bool,
+    timeout: Option<Duration>,
+    sink_buffer: usize,
 }
 
 impl Crossbar {
+    pub fn builder(endpoint: impl Into<String>, api_key: impl Into<String>) -> CrossbarBuilder {
+        CrossbarBuilder::new(endpoint, api_key)
+    }
+
+    /// Connects to the crossbar API. `https://` endpoints are verified against the system roots.
     pub async fn new(endpoint: String, api_key: String) -> Result<Crossbar> {
-        let channel = Endpoint::from_str(&endpoint)?.connect().await?;
+        CrossbarBuilder::new(endpoint, api_key).connect().await
+    }
 
-        let client = CrossbarClient::with_interceptor(channel, AuthInterceptor::new(api_key));
+    /// Connects over TLS with a custom CA bundle and/or client certificate (mutual TLS).
+    pub async fn with_tls(
+        endpoint: String,
+        api_key: String,
+        tls: ClientTlsConfig,
+    ) -> Result<Crossbar> {
+        CrossbarBuilder::new(endpoint, api_key)
+            .tls(tls)
+            .connect()
+            .await
+    }
 
-        Ok(Crossbar { client })
+    /// A unary request carrying the configured deadline.
+    fn unary<T>(&self, message: T, timeout: Option<Duration>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(timeout) = timeout {
+            request.set_timeout(timeout);
+        }
+        request
     }
 
-    pub async fn push(&mut self, req: PushRequest) -> Result<PushResponse> {
-        let response = self.client.push(Request::new(req)).await?;
-        let response = response.into_inner();
-        Ok(response)
+    async fn call<T, F, Fut>(&self, call: F) -> Result<T>
+    where
+        F: FnMut(CrossbarRpc) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        self.call_with(&self.retry, call).await
     }
 
-    pub async fn push_text(&mut self, topic: String, text: String) -> Result<PushResponse> {
-        let req = PushRequest {
-            topic,
-            message: Some(push_request::Message::Text(PushText { message: text })),
-        };
-        self.push(req).await
+    async fn call_with<T, F, Fut>(&self, retry: &RetryPolicy, mut call: F) -> Result<T>
+    where
+        F: FnMut(CrossbarRpc) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut attempt = 0;
+        let mut backoff = retry.initial_backoff;
+
+        loop {
+            match call(self.client.clone()).await {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status)
+                    if status.code() == Code::Unavailable && attempt < retry.max_retries =>
+                {
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(retry.max_backoff);
+                }
+                Err(status) => return Err(status.into()),
+            }
+        }
     }
 
-    pub async fn push_binary(&mut self, topic: String, bytes: Vec<u8>) -> Result<PushResponse> {
-        let req = PushRequest {
-            topic,
-            message: Some(push_request::Message::Binary(PushBinary { message: bytes })),
+    fn fill_idempotency_key(&self, req: &mut PushRequest) {
+        if self.idempotency_keys && req.idempotency_key.is_empty() {
+            req.idempotency_key = gen_uuid();
+        }
+    }
+
+    fn push_retry<'a>(&self, reqs: impl IntoIterator<Item = &'a PushRequest>) -> RetryPolicy {
+        match reqs.into_iter().all(|req| !req.idempotency_key.is_empty()) {
+            true => self.retry.clone(),
+            false => RetryPolicy::none(),
+        }
+    }
+
+    /// Pushes with an idempotency key that fail with `Unavailable` are retried. Ones without a
+    /// key are sent once, since a retry could deliver them twice, unless the builder enabled
+    /// `idempotency_keys`.
+    pub async fn push(&self, mut req: PushRequest) -> Result<PushResponse> {
+        self.fill_idempotency_key(&mut req);
+        let retry = self.push_retry([&req]);
+        self.call_with(&retry, |mut client| {
+            let req = self.unary(req.clone(), self.timeout);
+            async move { client.push(req).await }
+        })
+        .await
+    }
+
+    pub async fn push_json<T: Serialize>(&self, topic: String, value: &T) -> Result<PushResponse> {
+        self.push(PushRequest::json(topic, value)?).await
+    }
+
+    /// Publishes every message, even when some fail; the results are in request order. Only
+    /// retried if every message has an idempotency key, like `push`.
+    pub async fn push_batch(&self, mut messages: Vec<PushRequest>) -> Result<Vec<PushResult>> {
+        for message in messages.iter_mut() {
+            self.fill_idempotency_key(message);
+        }
+        let retry = self.push_retry(&messages);
+        let req = PushBatchRequest { messages };
+        let response = self
+            .call_with(&retry, |mut client| {
+                let req = self.unary(req.clone(), self.timeout);
+                async move { client.push_batch(req).await }
+            })
+            .await?;
+        Ok(response.results)
+    }
+
+    /// Opens a `PushStream` and returns a sink feeding it, for pushing many messages without
+    /// waiting on each. The stream isn't retried; `finish` reports whether it went through.
+    pub fn push_sink(&self) -> PushSink {
+        let (sender, receiver) = mpsc::channel(self.sink_buffer);
+        let mut client = self.client.clone();
+        let response = tokio::spawn(async move {
+            client
+                .push_stream(Request::new(receiver))
+                .await
+                .map(Response::into_inner)
+        });
+
+        PushSink { sender, response }
+    }
+
+    pub async fn push_text(&self, topic: String, text: String) -> Result<PushResponse> {
+        self.push(PushRequest::text(topic, text)).await
+    }
+
+    pub async fn push_binary(&self, topic: String, bytes: Vec<u8>) -> Result<PushResponse> {
+        self.push(PushRequest::binary(topic, bytes)).await
+    }
+
+    /// Like `push_text`, but only delivered to the connections matching `target`.
+    pub async fn push_text_to(
+        &self,
+        target: PushTarget,
+        topic: String,
+        text: String,
+    ) -> Result<PushResponse> {
+        self.push(PushRequest::text(topic, text).with_target(target))
+            .await
+    }
+
+    /// Like `push_binary`, but only delivered to the connections matching `target`.
+    pub async fn push_binary_to(
+        &self,
+        target: PushTarget,
+        topic: String,
+        bytes: Vec<u8>,
+    ) -> Result<PushResponse> {
+        self.push(PushRequest::binary(topic, bytes).with_target(target))
+            .await
+    }
+
+    /// Streams crossbar messages, including the ones guest apps publish. Empty `topics` or
+    /// `apps` match everything; filtering by app leaves out global messages.
+    pub async fn subscribe(
+        &self,
+        topics: Vec<String>,
+        apps: Vec<String>,
+    ) -> Result<Streaming<CrossbarEvent>> {
+        let req = SubscribeRequest { topics, apps };
+        self.call(|mut client| {
+            let req = req.clone();
+            async move { client.subscribe(Request::new(req)).await }
+        })
+        .await
+    }
+
+    /// Runs the `#[unit::request(name = ...)]` handler of one live instance matching `target`
+    /// and returns its reply. `None` uses the API's default timeout; the client's own deadline is
+    /// stretched to cover a longer one.
+    pub async fn request(
+        &self,
+        target: PushTarget,
+        name: String,
+        payload: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        let req = GuestRequest {
+            name,
+            target: Some(target),
+            payload,
+            timeout_ms: timeout.map(|t| t.as_millis() as u64).unwrap_or(0),
+        };
+        let deadline = match (self.timeout, timeout) {
+            (Some(deadline), Some(timeout)) => Some(deadline.max(timeout)),
+            (deadline, _) => deadline,
         };
-        self.push(req).await
+        let response = self
+            .call(|mut client| {
+                let req = self.unary(req.clone(), deadline);
+                async move { client.request(req).await }
+            })
+            .await?;
+        Ok(response.payload)
+    }
+}
+
+/// Buffered pushes over one `PushStream`; see `Crossbar::push_sink`.
+pub struct PushSink {
+    sender: mpsc::Sender<PushRequest>,
+    response: JoinHandle<Result<PushResponse, Status>>,
+}
+
+impl PushSink {
+    /// Ends the stream once the buffered messages are sent and waits for the API to accept it.
+    pub async fn finish(mut self) -> Result<()> {
+        self.sender.close_channel();
+
+        match self.response.await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(status)) => Err(status.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Sink<PushRequest> for PushSink {
+    type Error = anyhow::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.sender)
+            .poll_ready(cx)
+            .map_err(Into::into)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: PushRequest) -> Result<()> {
+        Pin::new(&mut self.sender)
+            .start_send(item)
+            .map_err(Into::into)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.sender)
+            .poll_flush(cx)
+            .map_err(Into::into)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.sender)
+            .poll_close(cx)
+            .map_err(Into::into)
     }
 }
@@ -1,42 +1,269 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
-use unit_utils::Result;
+use unit_utils::{err::bail, gen_uuid, Result};
 
+/// Channel of the global namespace, which every node listens on.
 pub static CROSSBAR_TOPIC: &str = "crossbar";
 
+static APP_CHANNEL_PREFIX: &str = "crossbar:app:";
+
+/// Channel of an app-scoped topic.
+pub fn app_channel(app_name: &str, topic: &str) -> String {
+    format!("{}{}:{}", APP_CHANNEL_PREFIX, app_name, topic)
+}
+
+/// Redis stream backing durable topics of the global namespace. Streams share the `{crossbar}`
+/// hash tag so nodes can read several with one command on Redis Cluster.
+pub static GLOBAL_STREAM: &str = "{crossbar}:stream:global";
+
+/// Redis stream backing an app's durable topics.
+pub fn app_stream(app_name: &str) -> String {
+    format!("{{crossbar}}:stream:app:{}", app_name)
+}
+
+/// Whether a channel carries crossbar messages (as opposed to requests or replies).
+pub fn is_message_channel(channel: &str) -> bool {
+    channel == CROSSBAR_TOPIC || channel.starts_with(APP_CHANNEL_PREFIX)
+}
+
+/// Channel prefix of every app-scoped topic.
+pub fn all_apps_channel_prefix() -> &'static str {
+    APP_CHANNEL_PREFIX
+}
+
+/// Channel prefix of every topic of an app.
+pub fn app_channel_prefix(app_name: &str) -> String {
+    format!("{}{}:", APP_CHANNEL_PREFIX, app_name)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum CrossbarContent {
     Text(String),
     Binary(Vec<u8>),
 }
 
+/// Narrows delivery to matching connections; every field that is set must match.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CrossbarTarget {
+    pub app_name: Option<String>,
+    pub connection_id: Option<String>,
+    /// User id the guest bound to its connection (see presence).
+    pub user_id: Option<String>,
+}
+
+impl CrossbarTarget {
+    pub fn matches(&self, app_name: &str, connection_id: &str, user_id: Option<&str>) -> bool {
+        let app_matches = self.app_name.as_deref().map_or(true, |app| app == app_name);
+        let connection_matches = self
+            .connection_id
+            .as_deref()
+            .map_or(true, |id| id == connection_id);
+        let user_matches = self
+            .user_id
+            .as_deref()
+            .map_or(true, |id| Some(id) == user_id);
+
+        app_matches && connection_matches && user_matches
+    }
+}
+
+/// Leading byte of versioned envelopes; unversioned ones start with the topic length.
+const ENVELOPE_MAGIC: u8 = 0xcb;
+
+/// Version of the envelope written by `encode_crossbar_message`.
+pub const ENVELOPE_VERSION: u8 = 1;
+
+pub fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CrossbarMessage {
     pub topic: String,
     pub content: CrossbarContent,
+    /// Trace context of the publisher (W3C `traceparent`/`tracestate`).
+    pub trace_context: Vec<(String, String)>,
+    pub target: CrossbarTarget,
+    /// Unique per message, assigned when it is created.
+    pub message_id: String,
+    /// Publish time in unix milliseconds.
+    pub timestamp_ms: u64,
+    /// Who published the message (`api`, `app:<name>` or `node:<id>`); set by the server.
+    pub publisher: Option<String>,
+    /// MIME type of the content, if the publisher gave one.
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+    /// Messages older than this are dropped instead of delivered.
+    pub ttl_ms: Option<u64>,
+    /// Id of the Redis Stream entry for durable messages; assigned by Redis, never encoded.
+    #[serde(skip)]
+    pub stream_id: Option<String>,
+}
+
+// unversioned envelope written by publishers that predate message metadata
+#[derive(Deserialize)]
+struct TargetedCrossbarMessage {
+    topic: String,
+    content: CrossbarContent,
+    trace_context: Vec<(String, String)>,
+    target: CrossbarTarget,
+}
+
+// envelope written by publishers that predate targeting
+#[derive(Deserialize)]
+struct TracedCrossbarMessage {
+    topic: String,
+    content: CrossbarContent,
+    trace_context: Vec<(String, String)>,
+}
+
+// envelope written by publishers that predate trace context
+#[derive(Deserialize)]
+struct LegacyCrossbarMessage {
+    topic: String,
+    content: CrossbarContent,
 }
 
 impl CrossbarMessage {
-    pub fn text(topic: String, message: String) -> Self {
+    pub fn new(topic: String, content: CrossbarContent) -> Self {
         Self {
             topic,
-            content: CrossbarContent::Text(message),
+            content,
+            trace_context: vec![],
+            target: CrossbarTarget::default(),
+            message_id: gen_uuid(),
+            timestamp_ms: unix_now_ms(),
+            publisher: None,
+            content_type: None,
+            headers: vec![],
+            ttl_ms: None,
+            stream_id: None,
         }
     }
 
+    pub fn text(topic: String, message: String) -> Self {
+        Self::new(topic, CrossbarContent::Text(message))
+    }
+
     pub fn binary(topic: String, message: Vec<u8>) -> Self {
-        Self {
-            topic,
-            content: CrossbarContent::Binary(message),
+        Self::new(topic, CrossbarContent::Binary(message))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.ttl_ms
+            .is_some_and(|ttl_ms| self.timestamp_ms.saturating_add(ttl_ms) < unix_now_ms())
+    }
+
+    /// Channel to publish on: the target app's namespace, or the global one.
+    pub fn channel(&self) -> String {
+        match self.target.app_name.as_deref() {
+            Some(app_name) => app_channel(app_name, &self.topic),
+            None => CROSSBAR_TOPIC.to_owned(),
+        }
+    }
+
+    /// Redis stream the message is appended to when its topic is durable.
+    pub fn stream(&self) -> String {
+        match self.target.app_name.as_deref() {
+            Some(app_name) => app_stream(app_name),
+            None => GLOBAL_STREAM.to_owned(),
         }
     }
 }
 
 pub fn encode_crossbar_message(message: CrossbarMessage) -> Result<Vec<u8>> {
-    let data = bincode::serialize(&message)?;
+    let mut data = vec![ENVELOPE_MAGIC, ENVELOPE_VERSION];
+    bincode::serialize_into(&mut data, &message)?;
     Ok(data)
 }
 
 pub fn decode_crossbar_message(data: Vec<u8>) -> Result<CrossbarMessage> {
-    let message = bincode::deserialize(&data)?;
-    Ok(message)
+    if let [ENVELOPE_MAGIC, version, rest @ ..] = data.as_slice() {
+        if *version == ENVELOPE_VERSION {
+            if let Ok(message) = bincode::deserialize(rest) {
+                return Ok(message);
+            }
+        } else if decode_unversioned(&data).is_err() {
+            bail!("Unsupported crossbar envelope version {}", version);
+        }
+    }
+
+    decode_unversioned(&data)
+}
+
+fn decode_unversioned(data: &[u8]) -> Result<CrossbarMessage> {
+    if let Ok(targeted) = bincode::deserialize::<TargetedCrossbarMessage>(data) {
+        return Ok(CrossbarMessage {
+            trace_context: targeted.trace_context,
+            target: targeted.target,
+            ..CrossbarMessage::new(targeted.topic, targeted.content)
+        });
+    }
+
+    if let Ok(traced) = bincode::deserialize::<TracedCrossbarMessage>(data) {
+        return Ok(CrossbarMessage {
+            trace_context: traced.trace_context,
+            ..CrossbarMessage::new(traced.topic, traced.content)
+        });
+    }
+
+    let legacy: LegacyCrossbarMessage = bincode::deserialize(data)?;
+    Ok(CrossbarMessage::new(legacy.topic, legacy.content))
+}
+
+/// Channel backend requests for guest handlers are published on.
+pub static CROSSBAR_REQUEST_CHANNEL: &str = "crossbar:request";
+
+/// Channel an API instance receives replies on.
+pub fn reply_channel(instance_id: &str) -> String {
+    format!("crossbar:reply:{}", instance_id)
+}
+
+/// A backend request for a guest `#[unit::request]` handler; answered by one matching instance.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CrossbarRequest {
+    pub request_id: String,
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub target: CrossbarTarget,
+    pub reply_channel: String,
+    /// How long the caller waits; the instance claim expires with it.
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum CrossbarReplyResult {
+    Ok(Vec<u8>),
+    NoHandler,
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CrossbarReply {
+    pub request_id: String,
+    pub result: CrossbarReplyResult,
+}
+
+pub fn encode_crossbar_request(request: &CrossbarRequest) -> Result<Vec<u8>> {
+    let data = bincode::serialize(request)?;
+    Ok(data)
+}
+
+pub fn decode_crossbar_request(data: &[u8]) -> Result<CrossbarRequest> {
+    let request = bincode::deserialize(data)?;
+    Ok(request)
+}
+
+pub fn encode_crossbar_reply(reply: &CrossbarReply) -> Result<Vec<u8>> {
+    let data = bincode::serialize(reply)?;
+    Ok(data)
+}
+
+pub fn decode_crossbar_reply(data: &[u8]) -> Result<CrossbarReply> {
+    let reply = bincode::deserialize(data)?;
+    Ok(reply)
 }
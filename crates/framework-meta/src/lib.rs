@@ -5,9 +5,14 @@ use proc_macro_error::{abort, proc_macro_error};
 use quote::quote;
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input, FnArg, PatIdent, PatType, Result,
+    parse_macro_input, FnArg, PatIdent, PatType, Result, Type,
+};
+use unit_abi::{
+    handlers::{encode_handler_record, Handler, HANDLERS_SECTION_NAME},
+    header::{encode_abi_section, AbiHeader, ABI_SECTION_NAME},
+    requests::{request_export_name, validate_request_name},
+    topics::{is_topic_pattern, topic_export_name, validate_topic_pattern},
 };
-use unit_abi::header::{encode_abi_header, AbiHeader};
 
 struct ApplicationMacroInput {
     name: syn::LitStr,
@@ -36,17 +41,19 @@ impl Parse for ApplicationMacroInput {
 pub fn application(item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as ApplicationMacroInput);
 
-    let Ok(magic_bytes) = encode_abi_header(&AbiHeader {
+    let Ok(section_bytes) = encode_abi_section(&AbiHeader {
         name: item.name.value(),
     }) else {
         abort!(Span::call_site(), "Failed to encode ABI header");
     };
 
-    let magic_header = syn::LitByteStr::new(&magic_bytes, Span::call_site());
+    let section_len = section_bytes.len();
+    let section_name = ABI_SECTION_NAME;
 
     quote! {
-        #[no_mangle]
-        static UNIT_MAGIC_HEADER: &'static [u8] = #magic_header;
+        #[used]
+        #[link_section = #section_name]
+        static UNIT_ABI_HEADER: [u8; #section_len] = [#(#section_bytes),*];
 
         unit::data! { pub runtime: unit::tokio::runtime::Runtime = unit::tokio::runtime::Builder::new_current_thread().build().unwrap() }
 
@@ -130,14 +137,48 @@ pub fn message(_attr: TokenStream, item: TokenStream) -> TokenStream {
     .into()
 }
 
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn authorize(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item_fn = syn::parse_macro_input!(item as syn::ItemFn);
+
+    let item_fn_name = &item_fn.sig.ident;
+
+    if item_fn.sig.inputs.len() != 1 {
+        abort! {
+            item_fn.sig.inputs,
+            "Authorize handler must take exactly one AuthorizeRequest argument."
+        }
+    }
+
+    quote! {
+        #[no_mangle]
+        pub extern "C" fn unit_authorize(ptr: i32, len: u32) -> i32 {
+            let request = unsafe {
+                let slice = ::std::slice::from_raw_parts(ptr as _, len as _);
+                unit::proto::decode_runtime_proto_message::<unit::proto::AuthorizeRequest>(slice.to_vec()).unwrap()
+            };
+
+            let response = crate::runtime().block_on(#item_fn_name(request));
+            unit::auth::respond(&response);
+
+            return 0;
+        }
+
+        #item_fn
+    }
+    .into()
+}
+
 #[derive(Debug, FromMeta)]
-struct TopicArgs {
-    name: String,
+struct HttpArgs {
+    method: String,
+    path: String,
 }
 
 #[proc_macro_attribute]
 #[proc_macro_error]
-pub fn topic(attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn http(attr: TokenStream, item: TokenStream) -> TokenStream {
     let attr_args = match NestedMeta::parse_meta_list(attr.into()) {
         Ok(v) => v,
         Err(e) => {
@@ -145,31 +186,112 @@ pub fn topic(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     };
 
+    let args = match HttpArgs::from_list(&attr_args) {
+        Ok(v) => v,
+        Err(e) => {
+            return TokenStream::from(Error::from(e).write_errors());
+        }
+    };
+
     let item_fn = syn::parse_macro_input!(item as syn::ItemFn);
-    let item_stmts = item_fn.block.stmts;
+    let item_fn_name = &item_fn.sig.ident;
 
     if item_fn.sig.inputs.len() != 1 {
         abort! {
             item_fn.sig.inputs,
-            "Topic function must have only one argument."
+            "HTTP handler must take exactly one HttpRequest argument."
+        }
+    }
+
+    if !args.path.starts_with('/') {
+        abort!(Span::call_site(), "HTTP handler path must start with '/'");
+    }
+
+    let extern_fn_name = format!("unit_http_{}", item_fn_name.to_string().to_lowercase());
+
+    let Ok(record) = encode_handler_record(&Handler::Http {
+        method: args.method.to_uppercase(),
+        path: args.path,
+        export: extern_fn_name.clone(),
+    }) else {
+        abort!(Span::call_site(), "Failed to encode HTTP handler");
+    };
+
+    let record_len = record.len();
+    let record_static_name = Ident::new(
+        &format!("UNIT_HANDLER_{}", extern_fn_name.to_uppercase()),
+        Span::call_site(),
+    );
+    let section_name = HANDLERS_SECTION_NAME;
+    let extern_fn_name = Ident::new(&extern_fn_name, Span::call_site());
+
+    quote! {
+        #[used]
+        #[link_section = #section_name]
+        static #record_static_name: [u8; #record_len] = [#(#record),*];
+
+        #[no_mangle]
+        pub extern "C" fn #extern_fn_name(ptr: i32, len: u32) -> i32 {
+            let request = unsafe {
+                let slice = ::std::slice::from_raw_parts(ptr as _, len as _);
+                unit::proto::decode_runtime_proto_message::<unit::proto::HttpRequest>(slice.to_vec()).unwrap()
+            };
+
+            let response = crate::runtime().block_on(#item_fn_name(request));
+            unit::http::respond(&response);
+
+            return 0;
         }
+
+        #item_fn
     }
+    .into()
+}
+
+#[derive(Debug, FromMeta)]
+struct TopicArgs {
+    name: String,
+}
 
-    let first_arg = &item_fn.sig.inputs[0];
-    let first_arg_name = match first_arg {
-        FnArg::Typed(PatType { pat, .. }) => match &**pat {
-            syn::Pat::Ident(PatIdent { ident, .. }) => ident,
+fn topic_arg(arg: &FnArg) -> (&Ident, &Type) {
+    match arg {
+        FnArg::Typed(PatType { pat, ty, .. }) => match &**pat {
+            syn::Pat::Ident(PatIdent { ident, .. }) => (ident, ty),
             _ => abort! {
                 pat,
                 "Topic function argument must be a named identifier."
             },
         },
         _ => abort! {
-            first_arg,
+            arg,
             "Topic function argument must be a named identifier."
         },
+    }
+}
+
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn topic(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr_args = match NestedMeta::parse_meta_list(attr.into()) {
+        Ok(v) => v,
+        Err(e) => {
+            return TokenStream::from(Error::from(e).write_errors());
+        }
     };
 
+    let item_fn = syn::parse_macro_input!(item as syn::ItemFn);
+    let item_stmts = item_fn.block.stmts;
+
+    if item_fn.sig.inputs.is_empty() || item_fn.sig.inputs.len() > 2 {
+        abort! {
+            item_fn.sig.inputs,
+            "Topic function must take the content and optionally the TopicParams."
+        }
+    }
+
+    let (first_arg_name, first_arg_type) = topic_arg(&item_fn.sig.inputs[0]);
+    let params_arg = item_fn.sig.inputs.iter().nth(1).map(topic_arg);
+
     // TODO: FromEvent trait
     // let first_arg_type = match first_arg {
     //     FnArg::Typed(PatType { ty, .. }) => match &**ty {
@@ -200,10 +322,50 @@ pub fn topic(attr: TokenStream, item: TokenStream) -> TokenStream {
     };
 
     let topic_name = args.name;
-    let extern_fn_name = format!("unit_topic_{}", topic_name.to_lowercase().replace("-", "_"));
+    let bind_params = params_arg.map(|(name, ty)| quote! { let #name: #ty = event.params; });
+
+    // patterns are matched by the node against the handler records; exact names are found
+    // by their export name
+    let mut handler_record = quote! {};
+    let extern_fn_name = if is_topic_pattern(&topic_name) {
+        if let Err(e) = validate_topic_pattern(&topic_name) {
+            abort!(Span::call_site(), "{}", e);
+        }
+
+        let extern_fn_name = format!(
+            "unit_pattern_{}",
+            item_fn.sig.ident.to_string().to_lowercase()
+        );
+        let Ok(record) = encode_handler_record(&Handler::Topic {
+            pattern: topic_name,
+            export: extern_fn_name.clone(),
+        }) else {
+            abort!(Span::call_site(), "Failed to encode topic handler");
+        };
+
+        let record_len = record.len();
+        let record_static_name = Ident::new(
+            &format!("UNIT_HANDLER_{}", extern_fn_name.to_uppercase()),
+            Span::call_site(),
+        );
+        let section_name = HANDLERS_SECTION_NAME;
+        handler_record = quote! {
+            #[used]
+            #[link_section = #section_name]
+            static #record_static_name: [u8; #record_len] = [#(#record),*];
+        };
+
+        extern_fn_name
+    } else {
+        topic_export_name(&topic_name)
+    };
     let extern_fn_name = Ident::new(&extern_fn_name, Span::call_site());
 
     quote! {
+        #handler_record
+
+        // exact topic exports keep the topic's case
+        #[allow(non_snake_case)]
         #[no_mangle]
         pub extern "C" fn #extern_fn_name(ptr: i32, len: u32) -> i32 {
             let event = unsafe {
@@ -211,14 +373,77 @@ pub fn topic(attr: TokenStream, item: TokenStream) -> TokenStream {
                 unit::proto::decode_runtime_proto_message::<unit::proto::CrossbarMessage>(slice.to_vec()).unwrap()
             };
 
-            let #first_arg_name = event.content;
+            unit::crossbar::set_current(&event);
+            let #first_arg_name: #first_arg_type = event.content;
+            #bind_params
 
             crate::runtime().block_on(async move {
                 #(#item_stmts)*
             });
+            // other handlers must not see it as theirs
+            unit::crossbar::clear_current();
 
             return 0;
         }
     }
     .into()
 }
+
+#[derive(Debug, FromMeta)]
+struct RequestArgs {
+    name: String,
+}
+
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr_args = match NestedMeta::parse_meta_list(attr.into()) {
+        Ok(v) => v,
+        Err(e) => {
+            return TokenStream::from(Error::from(e).write_errors());
+        }
+    };
+
+    let args = match RequestArgs::from_list(&attr_args) {
+        Ok(v) => v,
+        Err(e) => {
+            return TokenStream::from(Error::from(e).write_errors());
+        }
+    };
+
+    let item_fn = syn::parse_macro_input!(item as syn::ItemFn);
+    let item_fn_name = &item_fn.sig.ident;
+
+    if item_fn.sig.inputs.len() != 1 {
+        abort! {
+            item_fn.sig.inputs,
+            "Request handler must take exactly one Vec<u8> payload argument."
+        }
+    }
+
+    if let Err(e) = validate_request_name(&args.name) {
+        abort!(Span::call_site(), "{}", e);
+    }
+
+    let extern_fn_name = Ident::new(&request_export_name(&args.name), Span::call_site());
+
+    quote! {
+        // request exports keep the name's case
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        pub extern "C" fn #extern_fn_name(ptr: i32, len: u32) -> i32 {
+            let payload = unsafe {
+                let slice = ::std::slice::from_raw_parts(ptr as _, len as _);
+                slice.to_vec()
+            };
+
+            let response = crate::runtime().block_on(#item_fn_name(payload));
+            unit::request::respond(&response);
+
+            return 0;
+        }
+
+        #item_fn
+    }
+    .into()
+}
@@ -0,0 +1,13 @@
+use unit_runtime_proto::{encode_runtime_proto_message, AuthorizeResponse};
+
+use crate::vm_internals;
+
+/// Returns `false` if the node rejected the response.
+pub fn respond(response: &AuthorizeResponse) -> bool {
+    let bytes = encode_runtime_proto_message(response).unwrap();
+
+    let result =
+        unsafe { vm_internals::unit_authorize_respond(bytes.as_ptr() as _, bytes.len() as _) };
+
+    result == 0
+}
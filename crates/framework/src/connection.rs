@@ -0,0 +1,15 @@
+use unit_runtime_proto::{decode_runtime_proto_message, ConnectionInfo};
+
+use crate::vm_internals;
+
+/// Metadata of the connection this instance serves: id, query string, headers, remote address and claims.
+pub fn info() -> ConnectionInfo {
+    let len = unsafe { vm_internals::unit_connection_info(0, 0) };
+
+    let mut bytes = vec![0u8; len as usize];
+    unsafe {
+        vm_internals::unit_connection_info(bytes.as_mut_ptr() as _, bytes.len() as _);
+    }
+
+    decode_runtime_proto_message(bytes).unwrap()
+}
@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+
+use unit_runtime_proto::{
+    decode_runtime_proto_message, encode_runtime_proto_message, CrossbarMessage, ReplayFrom,
+    ReplayRequest,
+};
+
+use crate::vm_internals;
+
+static CURRENT: Mutex<Option<CrossbarMessage>> = Mutex::new(None);
+
+#[doc(hidden)]
+pub fn set_current(message: &CrossbarMessage) {
+    *CURRENT.lock().unwrap() = Some(message.clone());
+}
+
+#[doc(hidden)]
+pub fn clear_current() {
+    *CURRENT.lock().unwrap() = None;
+}
+
+/// The message the current topic handler is running for, with its metadata and headers.
+pub fn current() -> Option<CrossbarMessage> {
+    CURRENT.lock().unwrap().clone()
+}
+
+/// Stream id of the durable message the current topic handler is running for. Hand it to
+/// clients so they can ask for a replay from there when they reconnect.
+pub fn current_id() -> Option<String> {
+    CURRENT.lock().unwrap().as_ref()?.stream_id.clone()
+}
+
+/// Durable messages this connection would have received since `from`, oldest first.
+/// Empty `topics` replays every durable topic.
+pub fn replay(from: ReplayFrom, topics: Vec<String>) -> Vec<CrossbarMessage> {
+    let request = ReplayRequest { from, topics };
+    let bytes = encode_runtime_proto_message(&request).unwrap();
+
+    let len = unsafe { vm_internals::unit_crossbar_replay(bytes.as_ptr() as _, bytes.len() as _) };
+    if len < 0 {
+        return vec![];
+    }
+
+    let mut result = vec![0u8; len as usize];
+    unsafe {
+        vm_internals::unit_host_result(result.as_mut_ptr() as _, result.len() as _);
+    }
+
+    decode_runtime_proto_message(result).unwrap_or_default()
+}
+
+/// Publishes on the crossbar in this app's namespace: other instances of the app and
+/// backends subscribed through the crossbar API receive it. The host fills in the message
+/// id, timestamp and publisher; content type, headers and TTL are kept. Returns `false`
+/// if the node rejected the message.
+pub fn publish(message: CrossbarMessage) -> bool {
+    let bytes = encode_runtime_proto_message(&message).unwrap();
+
+    let result =
+        unsafe { vm_internals::unit_crossbar_publish(bytes.as_ptr() as _, bytes.len() as _) };
+
+    result == 0
+}
+
+pub fn publish_text(topic: String, text: String) -> bool {
+    publish(CrossbarMessage::text(topic, text))
+}
+
+pub fn publish_bytes(topic: String, bytes: Vec<u8>) -> bool {
+    publish(CrossbarMessage::binary(topic, bytes))
+}
@@ -0,0 +1,12 @@
+use unit_runtime_proto::{encode_runtime_proto_message, HttpResponse};
+
+use crate::vm_internals;
+
+/// Returns `false` if the node rejected the response.
+pub fn respond(response: &HttpResponse) -> bool {
+    let bytes = encode_runtime_proto_message(response).unwrap();
+
+    let result = unsafe { vm_internals::unit_http_respond(bytes.as_ptr() as _, bytes.len() as _) };
+
+    result == 0
+}
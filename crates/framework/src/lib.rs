@@ -1,12 +1,22 @@
-pub use meta::{application, cleanup, init, message, topic};
+pub use meta::{application, authorize, cleanup, http, init, message, request, topic};
 pub use unit_meta as meta;
 
-pub use proto::{CrossbarContent, CrossbarMessage, WsMessage as Message};
+pub use proto::{
+    AuthorizeRequest, AuthorizeResponse, ConnectionInfo, CrossbarContent, CrossbarMessage,
+    HttpRequest, HttpResponse, Presence, PresenceUpdate, ReplayFrom, TopicParams,
+    WsMessage as Message,
+};
 pub use unit_runtime_proto as proto;
 
+pub mod auth;
 pub mod client;
+pub mod connection;
+pub mod crossbar;
 pub mod data;
+pub mod http;
 pub mod log;
+pub mod presence;
+pub mod request;
 pub mod vm_internals;
 
 pub use serde;
@@ -0,0 +1,57 @@
+use unit_runtime_proto::{
+    decode_runtime_proto_message, encode_runtime_proto_message, CrossbarContent, Presence,
+    PresenceUpdate,
+};
+
+use crate::vm_internals;
+
+/// Updates the presence of the current connection; `None` fields are left unchanged.
+/// Returns `false` if the node rejected the update.
+pub fn update(update: &PresenceUpdate) -> bool {
+    let bytes = encode_runtime_proto_message(update).unwrap();
+    let result =
+        unsafe { vm_internals::unit_presence_update(bytes.as_ptr() as _, bytes.len() as _) };
+
+    result == 0
+}
+
+pub fn set_user(user_id: &str) -> bool {
+    update(&PresenceUpdate {
+        user_id: Some(user_id.to_owned()),
+        ..Default::default()
+    })
+}
+
+pub fn join_room(room: &str) -> bool {
+    update(&PresenceUpdate {
+        room: Some(room.to_owned()),
+        ..Default::default()
+    })
+}
+
+/// Every connection of this app across the cluster. Empty if the node couldn't reach Redis.
+pub fn list() -> Vec<Presence> {
+    let len = unsafe { vm_internals::unit_presence_list() };
+    if len < 0 {
+        return vec![];
+    }
+
+    let mut bytes = vec![0u8; len as usize];
+    unsafe {
+        vm_internals::unit_host_result(bytes.as_mut_ptr() as _, bytes.len() as _);
+    }
+
+    decode_runtime_proto_message(bytes).unwrap_or_default()
+}
+
+pub fn list_room(room: &str) -> Vec<Presence> {
+    list()
+        .into_iter()
+        .filter(|presence| presence.room.as_deref() == Some(room))
+        .collect()
+}
+
+/// Decodes the content of a `presence-join` / `presence-leave` topic message.
+pub fn from_event(content: CrossbarContent) -> Option<Presence> {
+    Presence::from_json(&content.to_string())
+}
@@ -0,0 +1,7 @@
+use crate::vm_internals;
+
+pub fn respond(payload: &[u8]) {
+    unsafe {
+        vm_internals::unit_request_respond(payload.as_ptr() as _, payload.len() as _);
+    }
+}
@@ -1,6 +1,15 @@
 extern "C" {
     pub fn unit_log(ptr: i32, len: i32);
     pub fn unit_send_message(ptr: i32, len: i32);
+    pub fn unit_http_respond(ptr: i32, len: i32) -> i32;
+    pub fn unit_authorize_respond(ptr: i32, len: i32) -> i32;
+    pub fn unit_connection_info(ptr: i32, len: i32) -> i32;
+    pub fn unit_request_respond(ptr: i32, len: i32);
+    pub fn unit_crossbar_publish(ptr: i32, len: i32) -> i32;
+    pub fn unit_crossbar_replay(ptr: i32, len: i32) -> i32;
+    pub fn unit_presence_update(ptr: i32, len: i32) -> i32;
+    pub fn unit_presence_list() -> i32;
+    pub fn unit_host_result(ptr: i32, len: i32);
 
     // pub fn unit_save_shared_object(index: i32, ptr: i32, len: i32);
     // pub fn unit_lock_shared_object(index: i32);
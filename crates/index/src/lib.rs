@@ -10,25 +10,57 @@ pub struct IndexEntry {
     pub abi_header: AbiHeader,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteKind {
+    /// An alternative name clients can use in place of the app name (e.g. `chat-beta`).
+    Alias,
+    /// A `Host` header value routed to the app.
+    Host,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RouteEntry {
+    pub kind: RouteKind,
+    pub name: String,
+    pub app_name: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct IndexData {
     pub entries: Vec<IndexEntry>,
+    pub routes: Vec<RouteEntry>,
 }
 
 impl IndexData {
     pub fn new() -> IndexData {
-        IndexData { entries: vec![] }
+        IndexData {
+            entries: vec![],
+            routes: vec![],
+        }
     }
 }
 
+// index files written before routes were introduced
+#[derive(Deserialize)]
+struct LegacyIndexData {
+    entries: Vec<IndexEntry>,
+}
+
 pub struct Index {
     storage_path: PathBuf,
     data: IndexData,
 }
 
 fn decode_index_data(bytes: &[u8]) -> Result<IndexData> {
-    let data = bincode::deserialize(bytes)?;
-    Ok(data)
+    if let Ok(data) = bincode::deserialize(bytes) {
+        return Ok(data);
+    }
+
+    let legacy: LegacyIndexData = bincode::deserialize(bytes)?;
+    Ok(IndexData {
+        entries: legacy.entries,
+        routes: vec![],
+    })
 }
 
 fn encode_index_data(data: &IndexData) -> Result<Vec<u8>> {
@@ -47,6 +79,28 @@ fn read_and_decode_index_data(path: PathBuf) -> Result<IndexData> {
     return decode_index_data(&bytes);
 }
 
+fn normalize_route_name(kind: RouteKind, name: &str) -> String {
+    match kind {
+        RouteKind::Host => authority_host(name).to_lowercase(),
+        RouteKind::Alias => name.to_owned(),
+    }
+}
+
+/// The host of a `Host` header value, without the port or the brackets of an IPv6 address.
+fn authority_host(authority: &str) -> &str {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+
+    match authority.rsplit_once(':') {
+        // more than one `:` is a bare IPv6 address, which can't carry a port
+        Some((host, port)) if !host.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => {
+            host
+        }
+        _ => authority,
+    }
+}
+
 impl Index {
     pub fn load(storage_location: String) -> Result<Index> {
         let storage_location: PathBuf = storage_location.parse()?;
@@ -122,4 +176,117 @@ impl Index {
 
         self.save()
     }
+
+    /// Finds an app by its name or by one of its aliases.
+    pub fn find_app(&self, name: &str) -> Option<&IndexEntry> {
+        if let Some(entry) = self.data.entries.iter().find(|e| e.abi_header.name == name) {
+            return Some(entry);
+        }
+
+        let route = self.find_route(RouteKind::Alias, name)?;
+        self.data
+            .entries
+            .iter()
+            .find(|e| e.abi_header.name == route.app_name)
+    }
+
+    /// Finds the app a `Host` header is routed to. The port, if any, is ignored.
+    pub fn find_app_by_host(&self, host: &str) -> Option<&IndexEntry> {
+        let route = self.find_route(RouteKind::Host, host)?;
+        self.data
+            .entries
+            .iter()
+            .find(|e| e.abi_header.name == route.app_name)
+    }
+
+    pub fn routes(&self) -> &Vec<RouteEntry> {
+        &self.data.routes
+    }
+
+    pub fn find_route(&self, kind: RouteKind, name: &str) -> Option<&RouteEntry> {
+        let name = normalize_route_name(kind, name);
+        self.data
+            .routes
+            .iter()
+            .find(|r| r.kind == kind && r.name == name)
+    }
+
+    pub fn add_or_update_route(&mut self, route: RouteEntry) -> Result<()> {
+        let route = RouteEntry {
+            name: normalize_route_name(route.kind, &route.name),
+            ..route
+        };
+
+        if route.kind == RouteKind::Alias
+            && self
+                .data
+                .entries
+                .iter()
+                .any(|e| e.abi_header.name == route.name)
+        {
+            bail!("Alias conflicts with an app name");
+        }
+
+        let index = self
+            .data
+            .routes
+            .iter()
+            .position(|r| r.kind == route.kind && r.name == route.name);
+
+        if let Some(index) = index {
+            self.data.routes[index] = route;
+        } else {
+            self.data.routes.push(route);
+        }
+
+        self.save()
+    }
+
+    pub fn remove_route(&mut self, kind: RouteKind, name: &str) -> Result<()> {
+        let name = normalize_route_name(kind, name);
+        let index = self
+            .data
+            .routes
+            .iter()
+            .position(|r| r.kind == kind && r.name == name);
+
+        let Some(index) = index else {
+            bail!("Route not found");
+        };
+
+        self.data.routes.remove(index);
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authority_hosts() {
+        let cases = [
+            ("example.com", "example.com"),
+            ("example.com:8080", "example.com"),
+            ("127.0.0.1:80", "127.0.0.1"),
+            ("[::1]:8080", "::1"),
+            ("[::1]", "::1"),
+            ("::1", "::1"),
+            ("fe80::1:2", "fe80::1:2"),
+        ];
+
+        for (authority, host) in cases {
+            assert_eq!(authority_host(authority), host, "{}", authority);
+        }
+    }
+
+    #[test]
+    fn host_routes_are_normalized() {
+        assert_eq!(
+            normalize_route_name(RouteKind::Host, "Example.COM:443"),
+            "example.com"
+        );
+        assert_eq!(normalize_route_name(RouteKind::Host, "[::1]"), "::1");
+        assert_eq!(normalize_route_name(RouteKind::Alias, "Chat"), "Chat");
+    }
 }
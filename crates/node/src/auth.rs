@@ -0,0 +1,229 @@
+use std::net::SocketAddr;
+
+use axum::{
+    http::{
+        header::{AUTHORIZATION, ORIGIN},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+use log::{error, info};
+use unit_runtime_proto::{AuthorizeRequest, ConnectionInfo};
+use unit_utils::{err::bail, gen_uuid, Result};
+
+use crate::{
+    bus::Bus,
+    config::{jwk_algorithm, CONFIG},
+    connections::Transport,
+    runtime::Runtime,
+    server::create_runtime,
+};
+
+/// Verifies a JWT against the local JWKS and returns its claims as JSON.
+fn verify_jwt(jwks: &JwkSet, token: &str) -> Result<String> {
+    let header = decode_header(token)?;
+
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+    let Some(jwk) = jwk else {
+        bail!("No matching key in JWKS");
+    };
+
+    // the header's `alg` is the token author's choice; only the key's own or an allowed
+    // one is accepted
+    let algorithms = match jwk_algorithm(jwk) {
+        Ok(Some(alg)) => vec![alg],
+        _ => CONFIG.jwt_algorithms.clone(),
+    };
+    if !algorithms.contains(&header.alg) {
+        bail!("Token algorithm {:?} is not allowed", header.alg);
+    }
+
+    let key = DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.algorithms = algorithms;
+    match CONFIG.jwt_audience.as_ref() {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+    if let Some(issuer) = CONFIG.jwt_issuer.as_ref() {
+        validation.set_issuer(&[issuer]);
+    }
+
+    let token = decode::<serde_json::Value>(token, &key, &validation)?;
+
+    Ok(token.claims.to_string())
+}
+
+fn bearer_token(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    let from_header = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_owned());
+
+    if from_header.is_some() {
+        return from_header;
+    }
+
+    // browsers can't set headers on WebSocket/EventSource requests
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value.to_owned())
+}
+
+fn origin_allowed(app_name: &str, origin: Option<&str>) -> bool {
+    let Some(allowed) = CONFIG.allowed_origins.get(app_name) else {
+        return true;
+    };
+
+    let Some(origin) = origin else {
+        return false;
+    };
+
+    let origin = origin.trim_end_matches('/');
+    allowed.iter().any(|o| o == "*" || o == origin)
+}
+
+fn reject(status: StatusCode, reason: &str) -> Response {
+    (status, reason.to_owned()).into_response()
+}
+
+/// A connection that passed authorization, with the app instance that will serve it.
+pub struct Authorized {
+    pub connection_id: String,
+    pub runtime: Runtime,
+}
+
+fn run_authorize(
+    app_name: String,
+    transport: Transport,
+    request: AuthorizeRequest,
+    bus: Bus,
+) -> std::result::Result<Authorized, Response> {
+    let connection_id = gen_uuid();
+
+    let mut runtime = match create_runtime(connection_id.clone(), bus, &app_name) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("[{}] failed to create runtime {:?}", connection_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    if let Err(e) = runtime.boot() {
+        error!("[{}] failed to boot runtime {:?}", connection_id, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    runtime.set_connection_info(ConnectionInfo {
+        connection_id: connection_id.clone(),
+        app_name,
+        transport: Some(transport.as_str().to_owned()),
+        query: request.query.clone(),
+        headers: request.headers.clone(),
+        remote_addr: request.remote_addr.clone(),
+        claims: request.claims.clone(),
+    });
+
+    let jwt_claims = request.claims.clone();
+
+    let claims = match runtime.authorize(request) {
+        Ok(None) => jwt_claims,
+        Ok(Some(response)) if response.accept => response.claims.or(jwt_claims),
+        Ok(Some(response)) => {
+            let reason = response.reason.unwrap_or("rejected".to_owned());
+            info!("[{}] connection rejected by app: {}", connection_id, reason);
+            return Err(reject(StatusCode::FORBIDDEN, &reason));
+        }
+        Err(e) => {
+            error!("[{}] authorize handler failed {:?}", connection_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    runtime.set_claims(claims);
+
+    info!(
+        "[{}] connection authorized (claims: {})",
+        connection_id,
+        runtime.claims().is_some()
+    );
+
+    Ok(Authorized {
+        connection_id,
+        runtime,
+    })
+}
+
+/// Runs the node's built-in checks (Origin allowlist, JWT) and the app's `#[unit::authorize]`
+/// handler for a new connection. On rejection, returns the HTTP response to send instead of upgrading.
+pub async fn authorize_connection(
+    app_name: String,
+    transport: Transport,
+    headers: &HeaderMap,
+    query: Option<String>,
+    remote_addr: SocketAddr,
+    bus: Bus,
+) -> std::result::Result<Authorized, Response> {
+    let origin = headers
+        .get(ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+
+    if !origin_allowed(&app_name, origin.as_deref()) {
+        return Err(reject(StatusCode::FORBIDDEN, "origin not allowed"));
+    }
+
+    let token = bearer_token(headers, query.as_deref());
+    let claims = match (CONFIG.jwks.as_ref(), token) {
+        (Some(jwks), Some(token)) => match verify_jwt(jwks, &token) {
+            Ok(claims) => Some(claims),
+            Err(e) => {
+                info!("rejected invalid token for {}: {}", app_name, e);
+                return Err(reject(StatusCode::UNAUTHORIZED, "invalid token"));
+            }
+        },
+        _ => None,
+    };
+
+    let token_required = CONFIG
+        .jwt_required_apps
+        .iter()
+        .any(|app| app == "*" || *app == app_name);
+    if token_required && claims.is_none() {
+        return Err(reject(StatusCode::UNAUTHORIZED, "missing token"));
+    }
+
+    let request = AuthorizeRequest {
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?;
+                Some((name.as_str().to_owned(), value.to_owned()))
+            })
+            .collect(),
+        query,
+        origin,
+        remote_addr: Some(remote_addr.to_string()),
+        claims,
+    };
+
+    let result =
+        tokio::task::spawn_blocking(move || run_authorize(app_name, transport, request, bus)).await;
+
+    match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!("authorize task error {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
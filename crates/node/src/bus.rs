@@ -1,7 +1,9 @@
 use axum::extract::ws::Message;
 use log::info;
 use tokio::sync::broadcast;
-use unit_crossbar::CrossbarMessage;
+use unit_crossbar::{CrossbarMessage, CrossbarRequest};
+
+use crate::metrics::BUS_DROPPED_MESSAGES;
 
 #[derive(Clone, Debug)]
 pub enum BusMessage {
@@ -20,6 +22,9 @@ pub enum BusMessage {
         message: Message,
     },
     CrossbarMessage(CrossbarMessage),
+    CrossbarRequest(CrossbarRequest),
+    /// The node is shutting down; every connection should close.
+    Shutdown,
 }
 
 #[derive(Clone)]
@@ -38,7 +43,9 @@ impl Bus {
     }
 
     pub fn send(&self, msg: BusMessage) {
-        let _ = self.channel.send(msg);
+        if self.channel.send(msg).is_err() {
+            BUS_DROPPED_MESSAGES.inc();
+        }
     }
 }
 
@@ -67,6 +74,12 @@ pub fn start_bus_monitor_task(bus: Bus) {
                 BusMessage::CrossbarMessage(msg) => {
                     info!("[{}] crossbar_message {:?}", msg.topic, msg.content);
                 }
+                BusMessage::CrossbarRequest(request) => {
+                    info!("[{}] crossbar_request {}", request.request_id, request.name);
+                }
+                BusMessage::Shutdown => {
+                    info!("closing connections for shutdown");
+                }
             };
         }
     });
@@ -1,6 +1,13 @@
+use std::{collections::HashMap, str::FromStr};
+
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet, PublicKeyUse},
+    Algorithm, DecodingKey,
+};
+use log::warn;
 use unit_utils::{
-    env, lazy_static,
-    shared_config::{self, ConfigRedis},
+    env, gen_uuid, lazy_static,
+    shared_config::{self, ConfigDurable, ConfigPubSub, ConfigTls},
 };
 
 lazy_static! {
@@ -11,7 +18,111 @@ lazy_static! {
 pub struct Config {
     pub storage_path: String,
     pub ws_port: u32,
-    pub redis: ConfigRedis,
+    pub metrics_port: u32,
+    pub node_id: String,
+    pub node_address: String,
+    pub heartbeat_interval_secs: u64,
+    pub tls: Option<ConfigTls>,
+    pub pubsub: ConfigPubSub,
+    pub poll_timeout_secs: u64,
+    pub poll_idle_timeout_secs: u64,
+    pub jwks: Option<JwkSet>,
+    /// Algorithms accepted for keys whose JWK doesn't name one.
+    pub jwt_algorithms: Vec<Algorithm>,
+    pub jwt_audience: Option<String>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_required_apps: Vec<String>,
+    pub allowed_origins: HashMap<String, Vec<String>>,
+    pub otlp_endpoint: Option<String>,
+    pub drain_timeout_secs: u64,
+    pub durable: ConfigDurable,
+}
+
+// UNIT_ALLOWED_ORIGINS=chat=https://a.example|https://b.example;admin=https://admin.example
+fn parse_allowed_origins(value: Option<String>) -> HashMap<String, Vec<String>> {
+    let mut allowed_origins = HashMap::new();
+
+    let Some(value) = value else {
+        return allowed_origins;
+    };
+
+    for rule in value.split(';').filter(|rule| !rule.trim().is_empty()) {
+        let Some((app, origins)) = rule.split_once('=') else {
+            panic!("{} is not a valid value for UNIT_ALLOWED_ORIGINS", rule);
+        };
+
+        let origins = origins
+            .split('|')
+            .map(|origin| origin.trim().trim_end_matches('/').to_owned())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        allowed_origins.insert(app.trim().to_owned(), origins);
+    }
+
+    allowed_origins
+}
+
+/// Loaded at startup so a bad file stops the node instead of its first connection. Keys that
+/// can't verify signatures (encryption keys, unsupported algorithms) are skipped.
+fn load_jwks(path: &str) -> JwkSet {
+    let Ok(data) = std::fs::read(path) else {
+        panic!("Failed to read JWKS file: {}", path);
+    };
+
+    let Ok(jwks) = serde_json::from_slice::<JwkSet>(&data) else {
+        panic!("Failed to parse JWKS file: {}", path);
+    };
+
+    let keys: Vec<Jwk> = jwks
+        .keys
+        .into_iter()
+        .filter(|jwk| {
+            let kid = jwk.common.key_id.as_deref().unwrap_or("<no kid>");
+
+            if matches!(jwk.common.public_key_use, Some(PublicKeyUse::Encryption)) {
+                warn!("Skipping encryption key {} in JWKS file: {}", kid, path);
+                return false;
+            }
+            if jwk_algorithm(jwk).is_err() {
+                warn!(
+                    "Skipping key {} with unsupported algorithm in JWKS file: {}",
+                    kid, path
+                );
+                return false;
+            }
+            if DecodingKey::from_jwk(jwk).is_err() {
+                warn!("Skipping unsupported key {} in JWKS file: {}", kid, path);
+                return false;
+            }
+
+            true
+        })
+        .collect();
+
+    if keys.is_empty() {
+        panic!("No usable signing key in JWKS file: {}", path);
+    }
+
+    JwkSet { keys }
+}
+
+/// The algorithm a JWK is restricted to, if it names one.
+pub fn jwk_algorithm(jwk: &Jwk) -> jsonwebtoken::errors::Result<Option<Algorithm>> {
+    match jwk.common.key_algorithm {
+        Some(alg) => Algorithm::from_str(&alg.to_string()).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn parse_jwt_algorithms(values: Vec<String>) -> Vec<Algorithm> {
+    values
+        .iter()
+        .map(|value| match Algorithm::from_str(value) {
+            Ok(alg) => alg,
+            Err(_) => panic!("{} is not a valid value for UNIT_JWT_ALGORITHMS", value),
+        })
+        .collect()
 }
 
 impl Config {
@@ -20,15 +131,63 @@ impl Config {
 
         let storage_path = shared_config::resolve_storage_path();
         let ws_port = env::value_or_default("UNIT_WS_PORT", 6447u32);
+        let metrics_port = env::value_or_default("UNIT_METRICS_PORT", 6450u32);
+        let tls = shared_config::resolve_tls("UNIT_WS");
 
-        let Some(redis_config) = shared_config::resolve_redis() else {
-            panic!("Failed to resolve redis config");
+        let durable = shared_config::resolve_durable();
+
+        // stream offsets are saved per node, so a random id would lose them on every restart;
+        // the storage path is shared with the API and other nodes, so it can't be kept there
+        let node_id = match env::optional_str("UNIT_NODE_ID") {
+            Some(node_id) => node_id,
+            None if durable.is_enabled() => {
+                panic!("UNIT_NODE_ID must be set when durable topics are enabled")
+            }
+            None => gen_uuid(),
         };
+        let node_address = env::optional_str("UNIT_NODE_ADDRESS").unwrap_or_else(|| {
+            let host = env::str_or_default("HOSTNAME", "localhost");
+            format!("{}:{}", host, ws_port)
+        });
+        let heartbeat_interval_secs = env::value_or_default("UNIT_HEARTBEAT_SECS", 5u64);
+
+        let poll_timeout_secs = env::value_or_default("UNIT_POLL_TIMEOUT_SECS", 25u64);
+        let poll_idle_timeout_secs = env::value_or_default("UNIT_POLL_IDLE_TIMEOUT_SECS", 60u64);
+
+        let jwks = env::optional_str("UNIT_JWKS_PATH").map(|path| load_jwks(&path));
+        let mut jwt_algorithms = parse_jwt_algorithms(env::list_or_empty("UNIT_JWT_ALGORITHMS"));
+        if jwt_algorithms.is_empty() {
+            jwt_algorithms = vec![Algorithm::RS256, Algorithm::ES256];
+        }
+        let jwt_audience = env::optional_str("UNIT_JWT_AUDIENCE");
+        let jwt_issuer = env::optional_str("UNIT_JWT_ISSUER");
+        let jwt_required_apps = env::list_or_empty("UNIT_JWT_REQUIRED_APPS");
+        let allowed_origins = parse_allowed_origins(env::optional_str("UNIT_ALLOWED_ORIGINS"));
+        let otlp_endpoint = env::optional_str("UNIT_OTLP_ENDPOINT");
+        let drain_timeout_secs = env::value_or_default("UNIT_DRAIN_TIMEOUT_SECS", 30u64);
+
+        let pubsub = shared_config::resolve_pubsub();
 
         Self {
             storage_path,
             ws_port,
-            redis: redis_config,
+            metrics_port,
+            tls,
+            node_id,
+            node_address,
+            heartbeat_interval_secs,
+            pubsub,
+            poll_timeout_secs,
+            poll_idle_timeout_secs,
+            jwks,
+            jwt_algorithms,
+            jwt_audience,
+            jwt_issuer,
+            jwt_required_apps,
+            allowed_origins,
+            otlp_endpoint,
+            drain_timeout_secs,
+            durable,
         }
     }
 
@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::metrics::ACTIVE_CONNECTIONS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    WebSocket,
+    Sse,
+    LongPolling,
+}
+
+impl Transport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::WebSocket => "websocket",
+            Transport::Sse => "sse",
+            Transport::LongPolling => "long-polling",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    pub connection_id: String,
+    pub app_name: String,
+    pub transport: Transport,
+}
+
+/// Live connections on this node, regardless of transport.
+#[derive(Clone, Default)]
+pub struct Connections {
+    inner: Arc<Mutex<HashMap<String, ConnectionInfo>>>,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, info: ConnectionInfo) {
+        let gauge =
+            ACTIVE_CONNECTIONS.with_label_values(&[&info.app_name, info.transport.as_str()]);
+
+        let previous = self
+            .inner
+            .lock()
+            .unwrap()
+            .insert(info.connection_id.clone(), info);
+
+        if previous.is_none() {
+            gauge.inc();
+        }
+    }
+
+    pub fn remove(&self, connection_id: &str) -> Option<ConnectionInfo> {
+        let info = self.inner.lock().unwrap().remove(connection_id)?;
+
+        ACTIVE_CONNECTIONS
+            .with_label_values(&[&info.app_name, info.transport.as_str()])
+            .dec();
+
+        Some(info)
+    }
+
+    pub fn get(&self, connection_id: &str) -> Option<ConnectionInfo> {
+        self.inner.lock().unwrap().get(connection_id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn count_by_app(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+
+        for info in self.inner.lock().unwrap().values() {
+            *counts.entry(info.app_name.clone()).or_insert(0) += 1;
+        }
+
+        counts
+    }
+}
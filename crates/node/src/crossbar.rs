@@ -1,33 +1,286 @@
+use std::{collections::HashMap, sync::OnceLock};
+
 use crate::{
     bus::{Bus, BusMessage},
     config::CONFIG,
+    metrics::CROSSBAR_MESSAGES,
+};
+use log::{error, info};
+use opentelemetry::{trace::SpanKind, KeyValue};
+use tokio::sync::broadcast::error::RecvError;
+use unit_crossbar::{
+    app_channel_prefix, decode_crossbar_message, decode_crossbar_request, encode_crossbar_message,
+    encode_crossbar_reply, CrossbarMessage, CrossbarReply, CrossbarReplyResult, CrossbarRequest,
+    CrossbarTarget, CROSSBAR_REQUEST_CHANNEL, CROSSBAR_TOPIC,
 };
-use log::info;
-use unit_crossbar::{decode_crossbar_message, CROSSBAR_TOPIC};
-use unit_pubsub::{PubSub, PubsubInterface, RedisValue};
+use unit_pubsub::{PubSubHealth, SharedPubSub};
+use unit_runtime_proto as proto;
+use unit_telemetry::{extract_context, inject_context, start_span};
 use unit_utils::Result;
 
+/// App channels this node listens on, counted by live instances of each app.
+struct AppSubscriptions {
+    pubsub: SharedPubSub,
+    // async lock so a subscribe and an unsubscribe for the same app can't interleave
+    instances: tokio::sync::Mutex<HashMap<String, usize>>,
+}
+
+static SUBSCRIPTIONS: OnceLock<AppSubscriptions> = OnceLock::new();
+
+fn to_guest_content(content: unit_crossbar::CrossbarContent) -> proto::CrossbarContent {
+    match content {
+        unit_crossbar::CrossbarContent::Text(text) => proto::CrossbarContent::Text(text),
+        unit_crossbar::CrossbarContent::Binary(bin) => proto::CrossbarContent::Binary(bin),
+    }
+}
+
+/// The message as a guest topic handler or replay sees it.
+pub fn to_guest_message(msg: CrossbarMessage) -> proto::CrossbarMessage {
+    proto::CrossbarMessage {
+        topic: msg.topic,
+        content: to_guest_content(msg.content),
+        stream_id: msg.stream_id,
+        message_id: msg.message_id,
+        timestamp_ms: msg.timestamp_ms,
+        publisher: msg.publisher,
+        content_type: msg.content_type,
+        headers: msg.headers,
+        ttl_ms: msg.ttl_ms,
+        params: proto::TopicParams::default(),
+    }
+}
+
+/// A message published by a guest; it goes out in the guest's own app namespace and the
+/// id, timestamp and publisher are ours to set.
+pub fn from_guest_message(app_name: &str, msg: proto::CrossbarMessage) -> CrossbarMessage {
+    let content = match msg.content {
+        proto::CrossbarContent::Text(text) => unit_crossbar::CrossbarContent::Text(text),
+        proto::CrossbarContent::Binary(bin) => unit_crossbar::CrossbarContent::Binary(bin),
+    };
+
+    CrossbarMessage {
+        target: CrossbarTarget {
+            app_name: Some(app_name.to_owned()),
+            ..Default::default()
+        },
+        publisher: Some(format!("app:{}", app_name)),
+        content_type: msg.content_type,
+        headers: msg.headers,
+        ttl_ms: msg.ttl_ms,
+        ..CrossbarMessage::new(msg.topic, content)
+    }
+}
+
+/// Hands a received message to the app instances on this node.
+pub fn dispatch(bus: &Bus, mut msg: CrossbarMessage) {
+    if msg.is_expired() {
+        return;
+    }
+
+    CROSSBAR_MESSAGES.inc();
+
+    // guest handler spans become children of this one
+    let cx = start_span(
+        "crossbar.receive",
+        SpanKind::Consumer,
+        &extract_context(&msg.trace_context),
+        vec![KeyValue::new("crossbar.topic", msg.topic.clone())],
+    );
+    msg.trace_context = inject_context(&cx);
+
+    bus.send(BusMessage::CrossbarMessage(msg));
+}
+
 pub async fn start_crossbar_monitor_task(bus: Bus) -> Result<()> {
-    let pubsub = PubSub::connect(CONFIG.redis.clone()).await?;
+    let pubsub = unit_pubsub::connect(&CONFIG.pubsub).await?;
 
-    let mut stream = pubsub.subscriber.on_message();
+    let mut stream = pubsub.on_message();
     tokio::spawn(async move {
-        while let Ok(msg) = stream.recv().await {
-            match msg.value {
-                RedisValue::String(text) => {
-                    let bytes = text.as_bytes();
-                    let bytes = bytes.to_vec();
-                    let Ok(msg) = decode_crossbar_message(bytes) else {
-                        continue;
-                    };
-                    bus.send(BusMessage::CrossbarMessage(msg));
+        loop {
+            let msg = match stream.recv().await {
+                Ok(msg) => msg,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => {
+                    error!("pub/sub connection closed, crossbar messages are no longer received");
+                    break;
                 }
-                _ => continue,
+            };
+            let bytes = msg.payload;
+
+            if msg.channel == CROSSBAR_REQUEST_CHANNEL {
+                let Ok(request) = decode_crossbar_request(&bytes) else {
+                    continue;
+                };
+
+                bus.send(BusMessage::CrossbarRequest(request));
+                continue;
+            }
+
+            let Ok(msg) = decode_crossbar_message(bytes) else {
+                continue;
+            };
+
+            // delivered by the durable consumer from the stream instead
+            if CONFIG.durable.is_durable(&msg.topic) {
+                continue;
             }
+
+            dispatch(&bus, msg);
         }
     });
-    pubsub.subscriber.subscribe(CROSSBAR_TOPIC).await?;
+    pubsub.subscribe(CROSSBAR_TOPIC).await?;
+    pubsub.subscribe(CROSSBAR_REQUEST_CHANNEL).await?;
+
+    let _ = SUBSCRIPTIONS.set(AppSubscriptions {
+        pubsub,
+        instances: tokio::sync::Mutex::new(HashMap::new()),
+    });
     info!("monitor task started");
 
     Ok(())
 }
+
+/// Health of the connection crossbar messages arrive on.
+pub fn pubsub_health() -> PubSubHealth {
+    match SUBSCRIPTIONS.get() {
+        Some(subscriptions) => subscriptions.pubsub.health(),
+        None => PubSubHealth::Reconnecting,
+    }
+}
+
+/// Starts receiving an app's topics when its first instance on this node starts.
+pub async fn watch_app(app_name: &str) {
+    let Some(subscriptions) = SUBSCRIPTIONS.get() else {
+        return;
+    };
+
+    let mut instances = subscriptions.instances.lock().await;
+    let count = instances.entry(app_name.to_owned()).or_insert(0);
+    *count += 1;
+    if *count > 1 {
+        return;
+    }
+
+    let prefix = app_channel_prefix(app_name);
+    if let Err(e) = subscriptions.pubsub.subscribe_prefix(&prefix).await {
+        error!("failed to subscribe to {} topics {:?}", app_name, e);
+    }
+}
+
+/// Apps with live instances on this node.
+pub async fn watched_apps() -> Vec<String> {
+    let Some(subscriptions) = SUBSCRIPTIONS.get() else {
+        return vec![];
+    };
+
+    subscriptions
+        .instances
+        .lock()
+        .await
+        .keys()
+        .cloned()
+        .collect()
+}
+
+/// Stops receiving an app's topics once its last instance on this node is gone.
+pub async fn unwatch_app(app_name: &str) {
+    let Some(subscriptions) = SUBSCRIPTIONS.get() else {
+        return;
+    };
+
+    let mut instances = subscriptions.instances.lock().await;
+    let Some(count) = instances.get_mut(app_name) else {
+        return;
+    };
+    *count -= 1;
+    if *count > 0 {
+        return;
+    }
+    instances.remove(app_name);
+
+    let prefix = app_channel_prefix(app_name);
+    if let Err(e) = subscriptions.pubsub.unsubscribe_prefix(&prefix).await {
+        error!("failed to unsubscribe from {} topics {:?}", app_name, e);
+    }
+}
+
+/// Publishes a message from a guest; the write happens in the background.
+pub fn publish(message: CrossbarMessage) {
+    let Some(subscriptions) = SUBSCRIPTIONS.get() else {
+        return;
+    };
+
+    let durable = CONFIG.durable.is_durable(&message.topic);
+    let channel = message.channel();
+    let stream = message.stream();
+    tokio::spawn(async move {
+        let bytes = match encode_crossbar_message(message) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("failed to encode crossbar message for {} {:?}", channel, e);
+                return;
+            }
+        };
+
+        // same as the API: durable messages are also published for Subscribe streams
+        let pubsub = &subscriptions.pubsub;
+        let mut result = Ok(());
+        if durable {
+            result = pubsub
+                .append(&stream, bytes.clone(), CONFIG.durable.max_len)
+                .await
+                .map(|_| ());
+        }
+        if result.is_ok() {
+            result = pubsub.publish(&channel, bytes).await.map(|_| ());
+        }
+
+        if let Err(e) = result {
+            error!("failed to publish crossbar message on {} {:?}", channel, e);
+        }
+    });
+}
+
+/// Makes this instance the one that answers `request`; false if another instance already did.
+pub async fn claim_request(request: &CrossbarRequest) -> bool {
+    let Some(subscriptions) = SUBSCRIPTIONS.get() else {
+        return false;
+    };
+
+    let key = format!("unit:request:{}", request.request_id);
+    match subscriptions.pubsub.claim(&key, request.timeout_ms).await {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            error!("failed to claim request {} {:?}", request.request_id, e);
+            false
+        }
+    }
+}
+
+/// Sends the answer to a claimed request back to the API instance waiting for it.
+pub fn reply(request: &CrossbarRequest, result: CrossbarReplyResult) {
+    let Some(subscriptions) = SUBSCRIPTIONS.get() else {
+        return;
+    };
+
+    let channel = request.reply_channel.clone();
+    let reply = CrossbarReply {
+        request_id: request.request_id.clone(),
+        result,
+    };
+
+    tokio::spawn(async move {
+        let result = match encode_crossbar_reply(&reply) {
+            Ok(bytes) => subscriptions
+                .pubsub
+                .publish(&channel, bytes)
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            error!("failed to reply to request {} {:?}", reply.request_id, e);
+        }
+    });
+}
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, sync::OnceLock, time::Duration};
+
+use log::{error, info};
+use unit_crossbar::{app_stream, decode_crossbar_message, CrossbarMessage, GLOBAL_STREAM};
+use unit_pubsub::{
+    streams::{parse_stream_id, StreamEntry},
+    PubSub, SharedPubSub,
+};
+use unit_runtime_proto::{ReplayFrom, ReplayRequest};
+use unit_utils::{err::bail, Result};
+
+use crate::{
+    bus::Bus,
+    config::CONFIG,
+    crossbar::{dispatch, watched_apps},
+    presence,
+};
+
+static PUBSUB: OnceLock<SharedPubSub> = OnceLock::new();
+
+/// Where reading a stream resumes: the offset this node saved before a restart, or else
+/// the current end so a newly watched app doesn't get its whole history.
+async fn initial_offset(pubsub: &dyn PubSub, stream: &str) -> Result<String> {
+    if let Some(offset) = pubsub.load_stream_offset(&CONFIG.node_id, stream).await? {
+        return Ok(offset);
+    }
+
+    pubsub.last_stream_id(stream).await
+}
+
+/// Reads durable topics from Redis Streams: the global stream and the streams of apps
+/// with live instances here. Offsets are saved per node, so set `UNIT_NODE_ID` to resume
+/// after a restart.
+pub async fn start_durable_consumer_task(bus: Bus) -> Result<()> {
+    if !CONFIG.durable.is_enabled() {
+        return Ok(());
+    }
+
+    let pubsub = unit_pubsub::connect(&CONFIG.pubsub).await?;
+    if pubsub.redis().is_none() {
+        bail!("UNIT_DURABLE_TOPICS needs the Redis pub/sub backend");
+    }
+    let reader = pubsub.stream_reader().await?;
+    let _ = PUBSUB.set(pubsub.clone());
+
+    tokio::spawn(async move {
+        let mut offsets: HashMap<String, String> = HashMap::new();
+
+        loop {
+            let mut streams = vec![GLOBAL_STREAM.to_owned()];
+            streams.extend(watched_apps().await.iter().map(|app| app_stream(app)));
+
+            // an app that left this node starts from the end when it comes back
+            let unwatched: Vec<String> = offsets
+                .keys()
+                .filter(|stream| !streams.contains(*stream))
+                .cloned()
+                .collect();
+            for stream in unwatched {
+                offsets.remove(&stream);
+                if let Err(e) = pubsub.remove_stream_offset(&CONFIG.node_id, &stream).await {
+                    error!("failed to remove offset of {} {:?}", stream, e);
+                }
+            }
+
+            for stream in streams.iter() {
+                if offsets.contains_key(stream) {
+                    continue;
+                }
+
+                match initial_offset(pubsub.as_ref(), stream).await {
+                    Ok(offset) => {
+                        offsets.insert(stream.clone(), offset);
+                    }
+                    Err(e) => error!("failed to resolve offset of {} {:?}", stream, e),
+                }
+            }
+
+            let positions: Vec<(String, String)> = streams
+                .iter()
+                .filter_map(|stream| Some((stream.clone(), offsets.get(stream)?.clone())))
+                .collect();
+
+            // short block so newly watched apps are picked up quickly
+            let entries = match reader.read(&positions, 1000, 100).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("failed to read crossbar streams {:?}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+
+            for (stream, entries) in entries {
+                let Some((last_id, _)) = entries.last() else {
+                    continue;
+                };
+                let last_id = last_id.clone();
+
+                for (id, bytes) in entries {
+                    let Ok(mut msg) = decode_crossbar_message(bytes) else {
+                        continue;
+                    };
+                    msg.stream_id = Some(id);
+
+                    dispatch(&bus, msg);
+                }
+
+                if let Err(e) = pubsub
+                    .save_stream_offset(&CONFIG.node_id, &stream, &last_id)
+                    .await
+                {
+                    error!("failed to save offset of {} {:?}", stream, e);
+                }
+                offsets.insert(stream, last_id);
+            }
+        }
+    });
+
+    info!("durable consumer task started");
+
+    Ok(())
+}
+
+fn replay_start(from: &ReplayFrom) -> String {
+    match from {
+        ReplayFrom::AfterId(id) => format!("({}", id),
+        ReplayFrom::Since(ms) => format!("{}-0", ms),
+    }
+}
+
+async fn read_replay(
+    pubsub: &dyn PubSub,
+    app_name: &str,
+    start: &str,
+    limit: u64,
+) -> Result<Vec<StreamEntry>> {
+    let mut entries = pubsub.read_range(GLOBAL_STREAM, start, limit).await?;
+    entries.extend(
+        pubsub
+            .read_range(&app_stream(app_name), start, limit)
+            .await?,
+    );
+
+    entries.sort_by_key(|(id, _)| parse_stream_id(id));
+
+    Ok(entries)
+}
+
+/// Durable messages a connection would have received, oldest first. Blocks, so it must
+/// run on a runtime worker or blocking thread.
+pub fn replay(
+    app_name: &str,
+    connection_id: &str,
+    request: &ReplayRequest,
+) -> Result<Vec<CrossbarMessage>> {
+    let Some(pubsub) = PUBSUB.get() else {
+        return Ok(vec![]);
+    };
+
+    let start = replay_start(&request.from);
+    let limit = CONFIG.durable.replay_limit;
+    let user_id = presence::user_id(connection_id);
+
+    let entries = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(read_replay(
+            pubsub.as_ref(),
+            app_name,
+            &start,
+            limit,
+        ))
+    })?;
+
+    let messages = entries
+        .into_iter()
+        .filter_map(|(id, bytes)| {
+            let mut msg = decode_crossbar_message(bytes).ok()?;
+            msg.stream_id = Some(id);
+            Some(msg)
+        })
+        .filter(|msg| !msg.is_expired())
+        .filter(|msg| request.topics.is_empty() || request.topics.contains(&msg.topic))
+        .filter(|msg| {
+            msg.target
+                .matches(app_name, connection_id, user_id.as_deref())
+        })
+        .take(limit as usize)
+        .collect();
+
+    Ok(messages)
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, RawQuery, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use log::{error, info};
+use unit_abi::handlers::Handler;
+use unit_index::IndexEntry;
+use unit_runtime_proto::{HttpRequest, HttpResponse};
+use unit_utils::{gen_uuid, Result};
+
+use crate::{
+    runtime::{load_module, Runtime, RuntimeEnv},
+    server::{resolve_app, WsState},
+};
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => path == prefix || path.starts_with(&format!("{prefix}/")),
+        None => pattern == path,
+    }
+}
+
+fn find_http_export(handlers: &[Handler], method: &str, path: &str) -> Option<String> {
+    handlers.iter().find_map(|handler| match handler {
+        Handler::Http {
+            method: handler_method,
+            path: handler_path,
+            export,
+        } if (handler_method == method || handler_method == "*")
+            && path_matches(handler_path, path) =>
+        {
+            Some(export.clone())
+        }
+        _ => None,
+    })
+}
+
+fn run_http_request(
+    index_entry: IndexEntry,
+    app_path: PathBuf,
+    request: HttpRequest,
+    state: WsState,
+) -> Result<Option<HttpResponse>> {
+    let app_name = index_entry.abi_header.name.clone();
+    let app_path = app_path.to_str().unwrap().to_owned();
+
+    let compiled = load_module(&app_name, &app_path)?;
+    let Some(export) = find_http_export(&compiled.handlers, &request.method, &request.path) else {
+        return Ok(None);
+    };
+
+    let connection_id = gen_uuid();
+    info!(
+        "[{}] http {} {} -> {}",
+        connection_id, request.method, request.path, export
+    );
+
+    let runtime_env = RuntimeEnv::new(connection_id, state.bus);
+    let mut runtime = Runtime::new(app_name, app_path, index_entry.abi_header, runtime_env)?;
+
+    // an HTTP request isn't a connection, so the app's init and cleanup handlers don't run
+    runtime.boot()?;
+    let Some(response) = runtime.http_request(&export, request)? else {
+        return Ok(Some(HttpResponse::text(
+            500,
+            "handler did not respond".to_owned(),
+        )));
+    };
+
+    Ok(Some(response))
+}
+
+fn into_axum_response(response: HttpResponse) -> Response {
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in response.headers {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) else {
+            continue;
+        };
+
+        headers.append(name, value);
+    }
+
+    (status, headers, response.body).into_response()
+}
+
+async fn handle_http(
+    app_name: String,
+    path: String,
+    method: Method,
+    headers: HeaderMap,
+    query: Option<String>,
+    body: Bytes,
+    state: WsState,
+) -> Response {
+    let request = HttpRequest {
+        method: method.as_str().to_owned(),
+        path,
+        query,
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?;
+                Some((name.as_str().to_owned(), value.to_owned()))
+            })
+            .collect(),
+        body: body.to_vec(),
+    };
+
+    let Ok((index_entry, app_path)) = resolve_app(&app_name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        run_http_request(index_entry, app_path, request, state)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(response))) => into_axum_response(response),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(e)) => {
+            error!("http runtime error {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            error!("http task error {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn http_handler(
+    Path((app, path)): Path<(String, String)>,
+    method: Method,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    State(state): State<WsState>,
+    body: Bytes,
+) -> Response {
+    let path = format!("/{}", path.trim_start_matches('/'));
+    handle_http(app, path, method, headers, query, body, state).await
+}
+
+pub async fn http_root_handler(
+    Path(app): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    State(state): State<WsState>,
+    body: Bytes,
+) -> Response {
+    handle_http(app, "/".to_owned(), method, headers, query, body, state).await
+}
@@ -1,14 +1,30 @@
+mod auth;
 mod bus;
 mod config;
+mod connections;
 mod crossbar;
+mod durable;
+mod http;
+mod metrics;
+mod poll;
+mod presence;
+mod registry;
 mod runtime;
 mod server;
+mod shutdown;
+mod sse;
+mod transport;
 
+use log::error;
+use unit_telemetry::{init_tracing, shutdown_tracing};
 use unit_utils::Result;
 
 use crate::{
     bus::{start_bus_monitor_task, Bus},
+    config::CONFIG,
     crossbar::start_crossbar_monitor_task,
+    durable::start_durable_consumer_task,
+    metrics::serve_metrics,
     server::serve_ws,
 };
 
@@ -22,12 +38,24 @@ fn setup_logger() {
 #[tokio::main]
 async fn main() -> Result<()> {
     setup_logger();
+    init_tracing("unit-node", CONFIG.otlp_endpoint.as_deref())?;
 
     let bus = Bus::new();
 
     start_bus_monitor_task(bus.clone());
     start_crossbar_monitor_task(bus.clone()).await?;
+    start_durable_consumer_task(bus.clone()).await?;
+
+    let metrics_addr = format!("0.0.0.0:{port}", port = CONFIG.metrics_port);
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics(metrics_addr).await {
+            error!("metrics server error {:?}", e);
+        }
+    });
+
     serve_ws("0.0.0.0:6447".to_owned(), bus).await?;
 
+    shutdown_tracing();
+
     Ok(())
 }
@@ -0,0 +1,92 @@
+use std::net::SocketAddr;
+
+use axum::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router, Server,
+};
+use log::{error, info};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use unit_utils::{lazy_static, Result};
+
+lazy_static! {
+    pub static ref ACTIVE_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "unit_node_active_connections",
+        "Connections currently served by this node",
+        &["app", "transport"]
+    )
+    .unwrap();
+    pub static ref INSTANCE_START_SECONDS: HistogramVec = register_histogram_vec!(
+        "unit_node_instance_start_seconds",
+        "Time to compile and instantiate an app module",
+        &["app"]
+    )
+    .unwrap();
+    pub static ref MODULE_COMPILE_SECONDS: HistogramVec = register_histogram_vec!(
+        "unit_node_module_compile_seconds",
+        "Time to compile an app module",
+        &["app"]
+    )
+    .unwrap();
+    pub static ref GUEST_CALL_SECONDS: HistogramVec = register_histogram_vec!(
+        "unit_node_guest_call_seconds",
+        "Duration of calls into guest exports",
+        &["app", "export"]
+    )
+    .unwrap();
+    pub static ref MESSAGES_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "unit_node_messages_received_total",
+        "Client messages delivered to apps",
+        &["app"]
+    )
+    .unwrap();
+    pub static ref MESSAGES_SENT: IntCounterVec = register_int_counter_vec!(
+        "unit_node_messages_sent_total",
+        "Messages sent by apps to their clients",
+        &["app"]
+    )
+    .unwrap();
+    pub static ref CROSSBAR_MESSAGES: IntCounter = register_int_counter!(
+        "unit_node_crossbar_messages_total",
+        "Crossbar messages received from pubsub"
+    )
+    .unwrap();
+    pub static ref BUS_LAGGED_MESSAGES: IntCounter = register_int_counter!(
+        "unit_node_bus_lagged_messages_total",
+        "Bus messages skipped by receivers that fell behind"
+    )
+    .unwrap();
+    pub static ref BUS_DROPPED_MESSAGES: IntCounter = register_int_counter!(
+        "unit_node_bus_dropped_messages_total",
+        "Bus messages sent while nothing was listening"
+    )
+    .unwrap();
+}
+
+async fn metrics_handler() -> Response {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("failed to encode metrics {:?}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    ([(CONTENT_TYPE, encoder.format_type().to_owned())], buffer).into_response()
+}
+
+pub async fn serve_metrics(addr: String) -> Result<()> {
+    let addr: SocketAddr = addr.parse()?;
+
+    let app = Router::new().route("/metrics", get(metrics_handler));
+
+    info!("metrics listening on http://{}/metrics", &addr);
+
+    Server::bind(&addr).serve(app.into_make_service()).await?;
+
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{ws::Message, ConnectInfo, Path, Query, RawQuery, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::info;
+use serde::Serialize;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+
+use crate::{
+    auth::authorize_connection,
+    bus::BusMessage,
+    config::CONFIG,
+    connections::Transport,
+    metrics::BUS_LAGGED_MESSAGES,
+    server::{resolve_request_app, spawn_connection, TransportQueryParams, WsState},
+    transport::{client_message, EncodedMessage},
+};
+
+type PollQueue = Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Message>>>;
+
+struct PollSession {
+    queue: PollQueue,
+    last_seen: Instant,
+}
+
+/// Outgoing message queues of long-polling connections, drained by `GET /poll/:connection_id`.
+/// Requests on a connection must carry its session token (see `SessionTokens`).
+#[derive(Clone, Default)]
+pub struct PollSessions {
+    inner: Arc<Mutex<HashMap<String, PollSession>>>,
+}
+
+impl PollSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&self, connection_id: String, queue: PollQueue) {
+        self.inner.lock().unwrap().insert(
+            connection_id,
+            PollSession {
+                queue,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    fn remove(&self, connection_id: &str) -> bool {
+        self.inner.lock().unwrap().remove(connection_id).is_some()
+    }
+
+    fn touch(&self, connection_id: &str) -> Option<PollQueue> {
+        let mut sessions = self.inner.lock().unwrap();
+        let session = sessions.get_mut(connection_id)?;
+        session.last_seen = Instant::now();
+
+        Some(session.queue.clone())
+    }
+
+    fn contains(&self, connection_id: &str) -> bool {
+        self.inner.lock().unwrap().contains_key(connection_id)
+    }
+
+    fn remove_idle(&self, idle_timeout: Duration) -> Vec<String> {
+        let mut sessions = self.inner.lock().unwrap();
+        let idle: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| session.last_seen.elapsed() > idle_timeout)
+            .map(|(connection_id, _)| connection_id.clone())
+            .collect();
+
+        for connection_id in idle.iter() {
+            sessions.remove(connection_id);
+        }
+
+        idle
+    }
+}
+
+#[derive(Serialize)]
+struct PollOpenResponse {
+    connection_id: String,
+    /// Sent back in the `x-unit-session` header of every later request on the connection.
+    session_token: String,
+}
+
+pub async fn poll_open_handler(
+    query: Query<TransportQueryParams>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    State(state): State<WsState>,
+) -> Response {
+    if state.draining.is_draining() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    let Some(app_name) = resolve_request_app(query.app.as_deref(), &headers) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let authorized = match authorize_connection(
+        app_name.clone(),
+        Transport::LongPolling,
+        &headers,
+        raw_query,
+        remote_addr,
+        state.bus.clone(),
+    )
+    .await
+    {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    let connection_id = authorized.connection_id.clone();
+    let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+    state.poll_sessions.insert(
+        connection_id.clone(),
+        Arc::new(tokio::sync::Mutex::new(queue_rx)),
+    );
+
+    let mut rx = state.bus.subscribe();
+    let root_connection_id = connection_id.clone();
+    let poll_sessions = state.poll_sessions.clone();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(BusMessage::TxWsMessage {
+                    connection_id,
+                    message,
+                }) if connection_id == root_connection_id => {
+                    let _ = queue_tx.send(message);
+                }
+                Ok(BusMessage::Kill { connection_id }) if connection_id == root_connection_id => {
+                    break;
+                }
+                Ok(BusMessage::Shutdown) => break,
+                Err(RecvError::Lagged(skipped)) => {
+                    BUS_LAGGED_MESSAGES.inc_by(skipped);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+
+        poll_sessions.remove(&root_connection_id);
+    });
+
+    let session_token = spawn_connection(app_name, authorized, Transport::LongPolling, state);
+
+    Json(PollOpenResponse {
+        connection_id,
+        session_token,
+    })
+    .into_response()
+}
+
+pub async fn poll_handler(
+    Path(connection_id): Path<String>,
+    State(state): State<WsState>,
+    headers: HeaderMap,
+) -> Response {
+    if !state.session_tokens.verify(&connection_id, &headers) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let Some(queue) = state.poll_sessions.touch(&connection_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut queue = queue.lock().await;
+    let timeout = Duration::from_secs(CONFIG.poll_timeout_secs);
+
+    let first = match tokio::time::timeout(timeout, queue.recv()).await {
+        Ok(Some(message)) => Some(message),
+        Ok(None) => return StatusCode::GONE.into_response(),
+        Err(_) => None,
+    };
+
+    let mut messages = vec![];
+    if let Some(message) = first {
+        messages.extend(EncodedMessage::from_message(message));
+
+        while let Ok(message) = queue.try_recv() {
+            messages.extend(EncodedMessage::from_message(message));
+        }
+    }
+
+    state.poll_sessions.touch(&connection_id);
+
+    Json(messages).into_response()
+}
+
+pub async fn poll_send_handler(
+    Path(connection_id): Path<String>,
+    State(state): State<WsState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if !state.session_tokens.verify(&connection_id, &headers)
+        || !state.poll_sessions.contains(&connection_id)
+    {
+        return StatusCode::NOT_FOUND;
+    }
+
+    state.bus.send(BusMessage::RxWsMessage {
+        connection_id,
+        message: client_message(&headers, body),
+    });
+
+    StatusCode::ACCEPTED
+}
+
+pub async fn poll_close_handler(
+    Path(connection_id): Path<String>,
+    State(state): State<WsState>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if !state.session_tokens.verify(&connection_id, &headers) {
+        return StatusCode::NOT_FOUND;
+    }
+
+    if !state.poll_sessions.remove(&connection_id) {
+        return StatusCode::NOT_FOUND;
+    }
+
+    state.bus.send(BusMessage::Kill { connection_id });
+
+    StatusCode::NO_CONTENT
+}
+
+/// Kills long-polling connections whose client stopped polling.
+pub fn start_poll_reaper_task(state: WsState) {
+    let idle_timeout = Duration::from_secs(CONFIG.poll_idle_timeout_secs);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+        loop {
+            interval.tick().await;
+
+            for connection_id in state.poll_sessions.remove_idle(idle_timeout) {
+                info!("[{}] long-polling client timed out", connection_id);
+                state.bus.send(BusMessage::Kill { connection_id });
+            }
+        }
+    });
+}
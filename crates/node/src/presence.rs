@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use log::error;
+use unit_crossbar::{encode_crossbar_message, CrossbarMessage, CrossbarTarget};
+use unit_pubsub::{nodes::unix_now, presence::PresenceRecord, SharedPubSub};
+use unit_runtime_proto::{Presence, PresenceUpdate, PRESENCE_JOIN_TOPIC, PRESENCE_LEAVE_TOPIC};
+use unit_utils::Result;
+
+use crate::config::CONFIG;
+
+/// Presence of this node's connections; the in-memory records are the source of truth and
+/// are re-published to the cluster on every heartbeat.
+struct PresenceStore {
+    pubsub: SharedPubSub,
+    records: Mutex<HashMap<String, PresenceRecord>>,
+}
+
+static PRESENCE: OnceLock<PresenceStore> = OnceLock::new();
+
+pub fn init_presence(pubsub: SharedPubSub) {
+    let _ = PRESENCE.set(PresenceStore {
+        pubsub,
+        records: Mutex::new(HashMap::new()),
+    });
+}
+
+fn store() -> Option<&'static PresenceStore> {
+    PRESENCE.get()
+}
+
+fn expires_at() -> u64 {
+    unix_now() + CONFIG.heartbeat_interval_secs * 3
+}
+
+fn to_presence(record: &PresenceRecord) -> Presence {
+    Presence {
+        connection_id: record.connection_id.clone(),
+        app_name: record.app_name.clone(),
+        node_id: record.node_id.clone(),
+        user_id: record.user_id.clone(),
+        room: record.room.clone(),
+        metadata: record.metadata.clone(),
+        joined_at: record.joined_at,
+    }
+}
+
+// the JWT `sub` claim is a sensible default until the app sets one
+fn user_id_from_claims(claims: Option<&str>) -> Option<String> {
+    let claims: serde_json::Value = serde_json::from_str(claims?).ok()?;
+    claims.get("sub")?.as_str().map(|sub| sub.to_owned())
+}
+
+async fn publish_event(store: &PresenceStore, topic: &str, record: &PresenceRecord) {
+    let message = CrossbarMessage {
+        target: CrossbarTarget {
+            app_name: Some(record.app_name.clone()),
+            ..Default::default()
+        },
+        publisher: Some(format!("node:{}", CONFIG.node_id)),
+        ..CrossbarMessage::text(topic.to_owned(), to_presence(record).to_json())
+    };
+    let channel = message.channel();
+
+    let result = match encode_crossbar_message(message) {
+        Ok(bytes) => store.pubsub.publish(&channel, bytes).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        error!(
+            "[{}] failed to publish {} {:?}",
+            record.connection_id, topic, e
+        );
+    }
+}
+
+pub async fn join(connection_id: &str, app_name: &str, claims: Option<&str>) {
+    let Some(store) = store() else {
+        return;
+    };
+
+    let record = PresenceRecord {
+        connection_id: connection_id.to_owned(),
+        app_name: app_name.to_owned(),
+        node_id: CONFIG.node_id.clone(),
+        user_id: user_id_from_claims(claims),
+        room: None,
+        metadata: None,
+        joined_at: unix_now(),
+        expires_at: expires_at(),
+    };
+
+    store
+        .records
+        .lock()
+        .unwrap()
+        .insert(connection_id.to_owned(), record.clone());
+
+    if let Err(e) = store.pubsub.set_presence(&record).await {
+        error!("[{}] failed to store presence {:?}", connection_id, e);
+    }
+
+    publish_event(store, PRESENCE_JOIN_TOPIC, &record).await;
+}
+
+pub async fn leave(connection_id: &str) {
+    let Some(store) = store() else {
+        return;
+    };
+
+    let Some(record) = store.records.lock().unwrap().remove(connection_id) else {
+        return;
+    };
+
+    if let Err(e) = store
+        .pubsub
+        .remove_presence(&record.app_name, connection_id)
+        .await
+    {
+        error!("[{}] failed to remove presence {:?}", connection_id, e);
+    }
+
+    publish_event(store, PRESENCE_LEAVE_TOPIC, &record).await;
+}
+
+/// Applies a guest's presence update; the write to the cluster happens in the background.
+pub fn update(connection_id: &str, update: PresenceUpdate) {
+    let Some(store) = store() else {
+        return;
+    };
+
+    let record = {
+        let mut records = store.records.lock().unwrap();
+        let Some(record) = records.get_mut(connection_id) else {
+            return;
+        };
+
+        if update.user_id.is_some() {
+            record.user_id = update.user_id;
+        }
+        if update.room.is_some() {
+            record.room = update.room;
+        }
+        if update.metadata.is_some() {
+            record.metadata = update.metadata;
+        }
+
+        record.clone()
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = store.pubsub.set_presence(&record).await {
+            error!(
+                "[{}] failed to store presence {:?}",
+                record.connection_id, e
+            );
+        }
+    });
+}
+
+/// User id bound to one of this node's connections.
+pub fn user_id(connection_id: &str) -> Option<String> {
+    store()?
+        .records
+        .lock()
+        .unwrap()
+        .get(connection_id)?
+        .user_id
+        .clone()
+}
+
+/// Cluster-wide presence for an app. Blocks, so it must run on a runtime worker or blocking thread.
+pub fn list(app_name: &str) -> Result<Vec<Presence>> {
+    let Some(store) = store() else {
+        return Ok(vec![]);
+    };
+
+    let records = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(store.pubsub.list_presence(app_name))
+    })?;
+
+    Ok(records.iter().map(to_presence).collect())
+}
+
+/// Extends the expiry of this node's presence records.
+pub async fn refresh() {
+    let Some(store) = store() else {
+        return;
+    };
+
+    let records: Vec<PresenceRecord> = store
+        .records
+        .lock()
+        .unwrap()
+        .values_mut()
+        .map(|record| {
+            record.expires_at = expires_at();
+            record.clone()
+        })
+        .collect();
+
+    for record in records {
+        if let Err(e) = store.pubsub.set_presence(&record).await {
+            error!(
+                "[{}] failed to refresh presence {:?}",
+                record.connection_id, e
+            );
+        }
+    }
+}
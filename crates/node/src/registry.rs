@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use log::{error, info};
+use unit_pubsub::{
+    nodes::{unix_now, NodeInfo},
+    SharedPubSub,
+};
+use unit_utils::Result;
+
+use crate::{config::CONFIG, presence, server::WsState};
+
+fn node_info(state: &WsState, started_at: u64) -> NodeInfo {
+    let now = unix_now();
+
+    NodeInfo {
+        id: CONFIG.node_id.clone(),
+        address: CONFIG.node_address.clone(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        started_at,
+        last_heartbeat: now,
+        expires_at: now + CONFIG.heartbeat_interval_secs * 3,
+        draining: state.draining.is_draining(),
+        connections: state
+            .connections
+            .count_by_app()
+            .into_iter()
+            .map(|(app, count)| (app, count as u64))
+            .collect(),
+    }
+}
+
+/// Registers this node in the cluster and keeps its heartbeat (and connection counts) fresh.
+pub async fn start_node_registry_task(state: WsState) -> Result<SharedPubSub> {
+    let pubsub = unit_pubsub::connect(&CONFIG.pubsub).await?;
+    let started_at = unix_now();
+
+    pubsub.register_node(&node_info(&state, started_at)).await?;
+    info!(
+        "registered node {} ({})",
+        CONFIG.node_id, CONFIG.node_address
+    );
+
+    let registry = pubsub.clone();
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(CONFIG.heartbeat_interval_secs));
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = registry.register_node(&node_info(&state, started_at)).await {
+                error!("failed to send node heartbeat {:?}", e);
+            }
+
+            presence::refresh().await;
+        }
+    });
+
+    Ok(pubsub)
+}
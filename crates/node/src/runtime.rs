@@ -1,30 +1,115 @@
-use crate::bus::{Bus, BusMessage};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use crate::{
+    bus::{Bus, BusMessage},
+    crossbar, durable,
+    metrics::{GUEST_CALL_SECONDS, INSTANCE_START_SECONDS, MESSAGES_SENT, MODULE_COMPILE_SECONDS},
+    presence,
+};
 use axum::extract::ws::Message;
-use log::info;
-use unit_abi::header::AbiHeader;
+use log::{error, info};
+use unit_abi::{
+    handlers::{decode_handlers, Handler},
+    header::AbiHeader,
+    requests::{legacy_request_export_name, request_export_name},
+    topics::{find_topic_export, match_topic},
+};
 use unit_runtime_proto::{
-    decode_runtime_proto_message, encode_runtime_proto_message, CrossbarMessage, WsMessage,
+    decode_runtime_proto_message, encode_runtime_proto_message, AuthorizeRequest,
+    AuthorizeResponse, ConnectionInfo, CrossbarMessage, HttpRequest, HttpResponse, PresenceUpdate,
+    ReplayRequest, TopicParams, WsMessage,
 };
-use unit_utils::Result;
+use unit_utils::{lazy_static, Result};
 use wasmer::{
-    imports, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory, Module, Store,
-    StoreMut, Value,
+    imports, Engine, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory, Module,
+    Store, StoreMut, Value,
 };
 use wasmer_wasix::{WasiEnv, WasiFunctionEnv};
 
+/// A compiled app module, shared by every instance of the same deploy.
+pub struct CompiledModule {
+    pub module: Module,
+    pub handlers: Vec<Handler>,
+    // modification time and length of the file it was compiled from
+    version: (SystemTime, u64),
+}
+
+lazy_static! {
+    static ref ENGINE: Engine = Engine::default();
+    static ref MODULES: Mutex<HashMap<String, Arc<CompiledModule>>> = Mutex::new(HashMap::new());
+}
+
+/// Compiles the module at `app_path`, or reuses the last compilation if the file hasn't changed
+/// since (a redeploy overwrites it in place).
+pub fn load_module(app_name: &str, app_path: &str) -> Result<Arc<CompiledModule>> {
+    let metadata = std::fs::metadata(app_path)?;
+    let version = (metadata.modified()?, metadata.len());
+
+    if let Some(compiled) = MODULES.lock().unwrap().get(app_path) {
+        if compiled.version == version {
+            return Ok(compiled.clone());
+        }
+    }
+
+    let started_at = Instant::now();
+
+    let app_bytes = std::fs::read(app_path)?;
+    let handlers = decode_handlers(&app_bytes)?;
+    let module = Module::new(&*ENGINE, app_bytes)?;
+
+    MODULE_COMPILE_SECONDS
+        .with_label_values(&[app_name])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    let compiled = Arc::new(CompiledModule {
+        module,
+        handlers,
+        version,
+    });
+    MODULES
+        .lock()
+        .unwrap()
+        .insert(app_path.to_owned(), compiled.clone());
+
+    Ok(compiled)
+}
+
 #[derive(Clone)]
 pub struct RuntimeEnv {
     pub connection_id: String,
     pub memory: Option<Memory>,
     pub bus: Bus,
+    pub http_response: Arc<Mutex<Option<HttpResponse>>>,
+    pub authorize_response: Arc<Mutex<Option<AuthorizeResponse>>>,
+    pub request_response: Arc<Mutex<Option<Vec<u8>>>>,
+    pub connection_info: Arc<Mutex<ConnectionInfo>>,
+    /// Trace of the event the guest is currently handling, stamped on its log records.
+    pub trace_id: Arc<Mutex<Option<String>>>,
+    /// Result of the last host call that returns data, fetched by the guest via `unit_host_result`.
+    pub host_result: Arc<Mutex<Option<Vec<u8>>>>,
 }
 
 impl RuntimeEnv {
     pub fn new(connection_id: String, bus: Bus) -> Self {
+        let connection_info = ConnectionInfo {
+            connection_id: connection_id.clone(),
+            ..Default::default()
+        };
+
         Self {
             memory: None,
             connection_id,
             bus,
+            http_response: Arc::new(Mutex::new(None)),
+            authorize_response: Arc::new(Mutex::new(None)),
+            request_response: Arc::new(Mutex::new(None)),
+            connection_info: Arc::new(Mutex::new(connection_info)),
+            trace_id: Arc::new(Mutex::new(None)),
+            host_result: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -39,7 +124,6 @@ impl RuntimeEnv {
         bytes_vec
     }
 
-    #[allow(unused)]
     pub fn write_memory(&self, store: &StoreMut, ptr: i32, bytes: &[u8]) {
         let memory = self.memory.as_ref().unwrap();
         memory.view(store).write(ptr as _, bytes).unwrap();
@@ -59,13 +143,19 @@ pub struct Runtime {
     pub wasi_env: WasiFunctionEnv,
     pub imports: Imports,
     pub instance: Instance,
+    /// `(pattern, export)` of the app's pattern topic handlers, in declaration order.
+    pub topic_patterns: Vec<(String, String)>,
 }
 
 fn unit_log(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) {
     let (env, store) = unit_env.data_and_store_mut();
     let bytes = env.read_memory(&store, ptr, len);
     let s = String::from_utf8(bytes.to_owned()).unwrap();
-    info!("[{}][log]: {}", env.connection_id, s);
+
+    match env.trace_id.lock().unwrap().as_ref() {
+        Some(trace_id) => info!("[{}][log][trace={}]: {}", env.connection_id, trace_id, s),
+        None => info!("[{}][log]: {}", env.connection_id, s),
+    }
 }
 
 fn unit_send_message(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) {
@@ -77,12 +167,174 @@ fn unit_send_message(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i3
         WsMessage::Binary(binary) => Message::Binary(binary),
     };
 
+    let app_name = env.connection_info.lock().unwrap().app_name.clone();
+    MESSAGES_SENT.with_label_values(&[&app_name]).inc();
+
     env.bus.send(BusMessage::TxWsMessage {
         connection_id: env.connection_id.clone(),
         message,
     });
 }
 
+// returns 0, or -1 if the message doesn't decode
+fn unit_crossbar_publish(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) -> i32 {
+    let (env, store) = unit_env.data_and_store_mut();
+    let bytes = env.read_memory(&store, ptr, len);
+    let message: CrossbarMessage = match decode_runtime_proto_message(bytes) {
+        Ok(message) => message,
+        Err(e) => {
+            error!("[{}] malformed crossbar message {:?}", env.connection_id, e);
+            return -1;
+        }
+    };
+    let app_name = env.connection_info.lock().unwrap().app_name.clone();
+
+    crossbar::publish(crossbar::from_guest_message(&app_name, message));
+
+    0
+}
+
+// returns 0, or -1 if the response doesn't decode (the request then fails with a 500)
+fn unit_http_respond(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) -> i32 {
+    let (env, store) = unit_env.data_and_store_mut();
+    let bytes = env.read_memory(&store, ptr, len);
+    let response: HttpResponse = match decode_runtime_proto_message(bytes) {
+        Ok(response) => response,
+        Err(e) => {
+            error!("[{}] malformed http response {:?}", env.connection_id, e);
+            return -1;
+        }
+    };
+
+    *env.http_response.lock().unwrap() = Some(response);
+
+    0
+}
+
+// returns 0, or -1 if the response doesn't decode (the connection is then rejected)
+fn unit_authorize_respond(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) -> i32 {
+    let (env, store) = unit_env.data_and_store_mut();
+    let bytes = env.read_memory(&store, ptr, len);
+    let response: AuthorizeResponse = match decode_runtime_proto_message(bytes) {
+        Ok(response) => response,
+        Err(e) => {
+            error!(
+                "[{}] malformed authorize response {:?}",
+                env.connection_id, e
+            );
+            return -1;
+        }
+    };
+
+    *env.authorize_response.lock().unwrap() = Some(response);
+
+    0
+}
+
+fn unit_request_respond(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) {
+    let (env, store) = unit_env.data_and_store_mut();
+    let bytes = env.read_memory(&store, ptr, len);
+
+    *env.request_response.lock().unwrap() = Some(bytes);
+}
+
+// returns the encoded length; the guest calls again with a large enough buffer
+fn unit_connection_info(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) -> i32 {
+    let (env, store) = unit_env.data_and_store_mut();
+    let info = env.connection_info.lock().unwrap().clone();
+    let bytes = encode_runtime_proto_message(&info).unwrap();
+
+    if bytes.len() <= len as usize {
+        env.write_memory(&store, ptr, &bytes);
+    }
+
+    bytes.len() as i32
+}
+
+// returns 0, or -1 if the update doesn't decode
+fn unit_presence_update(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) -> i32 {
+    let (env, store) = unit_env.data_and_store_mut();
+    let bytes = env.read_memory(&store, ptr, len);
+    let update: PresenceUpdate = match decode_runtime_proto_message(bytes) {
+        Ok(update) => update,
+        Err(e) => {
+            error!("[{}] malformed presence update {:?}", env.connection_id, e);
+            return -1;
+        }
+    };
+
+    presence::update(&env.connection_id, update);
+
+    0
+}
+
+// returns the length of the result to fetch with unit_host_result, or -1 on failure
+fn unit_presence_list(unit_env: FunctionEnvMut<RuntimeEnv>) -> i32 {
+    let env = unit_env.data();
+    let app_name = env.connection_info.lock().unwrap().app_name.clone();
+
+    let bytes = match presence::list(&app_name).and_then(|list| encode_runtime_proto_message(&list))
+    {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("[{}] failed to list presence {:?}", env.connection_id, e);
+            return -1;
+        }
+    };
+
+    let len = bytes.len() as i32;
+    *env.host_result.lock().unwrap() = Some(bytes);
+
+    len
+}
+
+// returns the length of the result to fetch with unit_host_result, or -1 on failure
+fn unit_crossbar_replay(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) -> i32 {
+    let (env, store) = unit_env.data_and_store_mut();
+    let bytes = env.read_memory(&store, ptr, len);
+    let request: ReplayRequest = match decode_runtime_proto_message(bytes) {
+        Ok(request) => request,
+        Err(e) => {
+            error!("[{}] malformed replay request {:?}", env.connection_id, e);
+            return -1;
+        }
+    };
+    let app_name = env.connection_info.lock().unwrap().app_name.clone();
+
+    let messages = match durable::replay(&app_name, &env.connection_id, &request) {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("[{}] failed to replay crossbar {:?}", env.connection_id, e);
+            return -1;
+        }
+    };
+
+    let messages: Vec<CrossbarMessage> = messages
+        .into_iter()
+        .map(crossbar::to_guest_message)
+        .collect();
+
+    let Ok(bytes) = encode_runtime_proto_message(&messages) else {
+        return -1;
+    };
+
+    let len = bytes.len() as i32;
+    *env.host_result.lock().unwrap() = Some(bytes);
+
+    len
+}
+
+fn unit_host_result(mut unit_env: FunctionEnvMut<RuntimeEnv>, ptr: i32, len: i32) {
+    let (env, store) = unit_env.data_and_store_mut();
+    let Some(bytes) = env.host_result.lock().unwrap().take() else {
+        return;
+    };
+
+    if bytes.len() <= len as usize {
+        env.write_memory(&store, ptr, &bytes);
+    }
+}
+
 impl Runtime {
     pub fn new(
         app_name: String,
@@ -90,15 +342,27 @@ impl Runtime {
         header: AbiHeader,
         mut runtime_env: RuntimeEnv,
     ) -> Result<Self> {
-        let app_bytes = std::fs::read(app_path.clone())?;
+        let started_at = Instant::now();
+
+        let compiled = load_module(&app_name, &app_path)?;
 
-        let mut store = Store::default();
-        let module = Module::new(&store, app_bytes)?;
+        let topic_patterns = compiled
+            .handlers
+            .iter()
+            .filter_map(|handler| match handler {
+                Handler::Topic { pattern, export } => Some((pattern.clone(), export.clone())),
+                _ => None,
+            })
+            .collect();
+
+        let mut store = Store::new(ENGINE.clone());
+        let module = compiled.module.clone();
 
         let memory_ty = module.imports().memories().next().map(|a| *a.ty()).unwrap();
         let memory = Memory::new(&mut store, memory_ty)?;
 
         runtime_env.initialize(memory.clone());
+        runtime_env.connection_info.lock().unwrap().app_name = app_name.clone();
 
         let runtime_env_instance = FunctionEnv::new(&mut store, runtime_env.clone());
         let mut wasi_env = WasiEnv::builder(app_name.clone()).finalize(&mut store)?;
@@ -107,6 +371,15 @@ impl Runtime {
             "env" => {
                 "unit_log" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_log),
                 "unit_send_message" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_send_message),
+                "unit_http_respond" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_http_respond),
+                "unit_authorize_respond" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_authorize_respond),
+                "unit_connection_info" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_connection_info),
+                "unit_request_respond" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_request_respond),
+                "unit_crossbar_replay" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_crossbar_replay),
+                "unit_crossbar_publish" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_crossbar_publish),
+                "unit_presence_update" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_presence_update),
+                "unit_presence_list" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_presence_list),
+                "unit_host_result" => Function::new_typed_with_env(&mut store, &runtime_env_instance, unit_host_result),
                 // "unit_save_shared_object" => Function::new_typed_with_env(&mut store, &unit_env_instance, unit_save_shared_object),
                 // "unit_lock_shared_object" => Function::new_typed_with_env(&mut store, &unit_env_instance, unit_lock_shared_object),
                 // "unit_unlock_shared_object" => Function::new_typed_with_env(&mut store, &unit_env_instance, unit_unlock_shared_object),
@@ -135,6 +408,10 @@ impl Runtime {
             true,
         )?;
 
+        INSTANCE_START_SECONDS
+            .with_label_values(&[&app_name])
+            .observe(started_at.elapsed().as_secs_f64());
+
         Ok(Self {
             connection_id: runtime_env.connection_id.clone(),
             app_name,
@@ -148,11 +425,15 @@ impl Runtime {
             wasi_env,
             imports: import_object,
             instance,
+            topic_patterns,
         })
     }
 
     fn call_fn(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>> {
         let fn_ = self.instance.exports.get_function(name).unwrap();
+        let _timer = GUEST_CALL_SECONDS
+            .with_label_values(&[&self.app_name, name])
+            .start_timer();
         let results = fn_.call(&mut self.store, args).unwrap();
 
         Ok(Vec::from(results))
@@ -166,6 +447,9 @@ impl Runtime {
         }
         let fn_ = fn_.unwrap();
 
+        let _timer = GUEST_CALL_SECONDS
+            .with_label_values(&[&self.app_name, name])
+            .start_timer();
         let results = fn_.call(&mut self.store, args).unwrap();
 
         Ok(Some(Vec::from(results)))
@@ -196,15 +480,78 @@ impl Runtime {
     }
 
     pub fn start(&mut self) -> Result<()> {
+        self.boot()?;
+        self.init()?;
+
+        Ok(())
+    }
+
+    /// Runs the module's `_start` without calling the app's init handler.
+    pub fn boot(&mut self) -> Result<()> {
         self.wasi_env.data(&self.store).thread.set_status_running();
 
         self.call_fn("_start", &[])?;
 
+        Ok(())
+    }
+
+    pub fn init(&mut self) -> Result<()> {
         self.call_fn_if_exists("unit_init", &[])?;
 
         Ok(())
     }
 
+    /// Asks the app whether to accept a connection. Returns `None` if the app has no authorize handler.
+    pub fn authorize(&mut self, req: AuthorizeRequest) -> Result<Option<AuthorizeResponse>> {
+        if self
+            .instance
+            .exports
+            .get_function("unit_authorize")
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        let encoded_request = encode_runtime_proto_message(&req)?;
+        let ptr = self.alloc_bytes(encoded_request.len())?;
+        self.write_mem(ptr, &encoded_request)?;
+
+        self.call_fn(
+            "unit_authorize",
+            &[
+                Value::I32(ptr as i32),
+                Value::I32(encoded_request.len() as i32),
+            ],
+        )?;
+
+        let response = self.runtime_env.authorize_response.lock().unwrap().take();
+
+        Ok(Some(response.unwrap_or_else(|| {
+            AuthorizeResponse::reject("authorize handler did not respond")
+        })))
+    }
+
+    pub fn set_connection_info(&mut self, info: ConnectionInfo) {
+        *self.runtime_env.connection_info.lock().unwrap() = info;
+    }
+
+    pub fn set_trace_id(&mut self, trace_id: Option<String>) {
+        *self.runtime_env.trace_id.lock().unwrap() = trace_id;
+    }
+
+    pub fn set_claims(&mut self, claims: Option<String>) {
+        self.runtime_env.connection_info.lock().unwrap().claims = claims;
+    }
+
+    pub fn claims(&self) -> Option<String> {
+        self.runtime_env
+            .connection_info
+            .lock()
+            .unwrap()
+            .claims
+            .clone()
+    }
+
     pub fn stop(&mut self) -> Result<()> {
         self.call_fn_if_exists("unit_cleanup", &[])?;
 
@@ -225,37 +572,86 @@ impl Runtime {
         Ok(())
     }
 
-    pub fn crossbar_event(&mut self, event: CrossbarMessage) -> Result<()> {
-        let encoded_event = encode_runtime_proto_message(&event)?;
-        let ptr = self.alloc_bytes(encoded_event.len())?;
-        self.write_mem(ptr, &encoded_event)?;
-
-        let normalized_event_fn_name = format!(
-            "unit_topic_{}",
-            event.topic.to_lowercase().replace("-", "_")
-        );
+    pub fn http_request(&mut self, export: &str, req: HttpRequest) -> Result<Option<HttpResponse>> {
+        let encoded_request = encode_runtime_proto_message(&req)?;
+        let ptr = self.alloc_bytes(encoded_request.len())?;
+        self.write_mem(ptr, &encoded_request)?;
 
-        let res = self.call_fn_if_exists(
-            &normalized_event_fn_name,
+        self.call_fn(
+            export,
             &[
                 Value::I32(ptr as i32),
-                Value::I32(encoded_event.len() as i32),
+                Value::I32(encoded_request.len() as i32),
             ],
         )?;
 
-        // we have a specialized handler for this topic
-        if res.is_some() {
-            return Ok(());
-        }
+        let response = self.runtime_env.http_response.lock().unwrap().take();
 
-        self.call_fn_if_exists(
-            "unit_event",
-            &[
-                Value::I32(ptr as i32),
-                Value::I32(encoded_event.len() as i32),
-            ],
+        Ok(response)
+    }
+
+    /// Calls the `#[unit::request]` handler for `name`; `None` if the app has no such handler.
+    /// Modules built before request exports were escaped are looked up by their legacy name.
+    pub fn request(&mut self, name: &str, payload: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let Some(export) = [request_export_name(name), legacy_request_export_name(name)]
+            .into_iter()
+            .find(|export| self.instance.exports.get_function(export).is_ok())
+        else {
+            return Ok(None);
+        };
+
+        let ptr = self.alloc_bytes(payload.len())?;
+        self.write_mem(ptr, &payload)?;
+
+        self.call_fn(
+            &export,
+            &[Value::I32(ptr as i32), Value::I32(payload.len() as i32)],
         )?;
 
+        let response = self.runtime_env.request_response.lock().unwrap().take();
+
+        Ok(Some(response.unwrap_or_default()))
+    }
+
+    /// Runs the handler for the exact topic and every pattern handler matching it, or the
+    /// catch-all `unit_event` if there are none.
+    pub fn crossbar_event(&mut self, event: CrossbarMessage) -> Result<()> {
+        let mut handlers = vec![];
+
+        if let Some(export) = find_topic_export(&event.topic, |export| {
+            self.instance.exports.get_function(export).is_ok()
+        }) {
+            handlers.push((export, event.clone()));
+        }
+
+        for (pattern, export) in self.topic_patterns.iter() {
+            let Some(params) = match_topic(pattern, &event.topic) else {
+                continue;
+            };
+
+            let mut event = event.clone();
+            event.params = TopicParams(params);
+            handlers.push((export.clone(), event));
+        }
+
+        if handlers.is_empty() {
+            handlers.push(("unit_event".to_owned(), event));
+        }
+
+        for (export, event) in handlers {
+            let encoded_event = encode_runtime_proto_message(&event)?;
+            let ptr = self.alloc_bytes(encoded_event.len())?;
+            self.write_mem(ptr, &encoded_event)?;
+
+            self.call_fn_if_exists(
+                &export,
+                &[
+                    Value::I32(ptr as i32),
+                    Value::I32(encoded_event.len() as i32),
+                ],
+            )?;
+        }
+
         Ok(())
     }
 }
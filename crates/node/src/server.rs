@@ -1,71 +1,201 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
-        Query, State, WebSocketUpgrade,
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        ConnectInfo, Path, Query, RawQuery, State, WebSocketUpgrade,
     },
-    response::IntoResponse,
-    routing::get,
+    http::{header::HOST, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{any, get, post},
     Router,
 };
+use axum_server::{tls_rustls::RustlsConfig, Handle};
 use futures::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
 use log::{error, info};
+use opentelemetry::{
+    trace::{SpanKind, Status as SpanStatus, TraceContextExt},
+    KeyValue,
+};
 use serde::Deserialize;
-use tokio::task::JoinHandle;
-use unit_index::Index;
-use unit_runtime_proto::{CrossbarContent, CrossbarMessage, WsMessage};
-use unit_utils::{err::bail, gen_uuid, Result};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use unit_crossbar::CrossbarReplyResult;
+use unit_index::{Index, IndexEntry};
+use unit_runtime_proto::WsMessage;
+use unit_telemetry::{extract_context, start_span, trace_id};
+use unit_tls::load_server_config;
+use unit_utils::{err::bail, Result};
 
 use crate::{
+    auth::{authorize_connection, Authorized},
     bus::{Bus, BusMessage},
     config::CONFIG,
+    connections::{ConnectionInfo, Connections, Transport},
+    crossbar::{claim_request, reply, to_guest_message, unwatch_app, watch_app},
+    http::{http_handler, http_root_handler},
+    metrics::{BUS_LAGGED_MESSAGES, MESSAGES_RECEIVED},
+    poll::{
+        poll_close_handler, poll_handler, poll_open_handler, poll_send_handler,
+        start_poll_reaper_task, PollSessions,
+    },
+    presence::{self, init_presence},
+    registry::start_node_registry_task,
     runtime::{Runtime, RuntimeEnv},
+    shutdown::{drain_on_shutdown, health_handler, ready_handler, Draining},
+    sse::{sse_handler, sse_send_handler},
+    transport::SessionTokens,
 };
 
 #[derive(Clone)]
 pub struct WsState {
-    bus: Bus,
+    pub bus: Bus,
+    pub connections: Connections,
+    pub poll_sessions: PollSessions,
+    pub session_tokens: SessionTokens,
+    pub draining: Draining,
 }
 
 impl WsState {
     pub fn new(bus: Bus) -> Self {
-        Self { bus }
+        Self {
+            bus,
+            connections: Connections::new(),
+            poll_sessions: PollSessions::new(),
+            session_tokens: SessionTokens::new(),
+            draining: Draining::new(),
+        }
     }
 }
 
 #[derive(Deserialize)]
-struct WsUpgradeQueryParams {
-    app: String,
+pub struct TransportQueryParams {
+    pub app: Option<String>,
 }
 
 pub async fn serve_ws(addr: String, bus: Bus) -> Result<()> {
     let addr = addr.parse()?;
     let state = WsState::new(bus);
 
+    start_poll_reaper_task(state.clone());
+    let registry = start_node_registry_task(state.clone()).await?;
+    init_presence(registry.clone());
+
     let app = Router::new()
         .route("/ws", get(ws_upgrade_handler))
-        .with_state(state);
+        .route("/ws/:app", get(ws_path_upgrade_handler))
+        .route("/sse", get(sse_handler))
+        .route("/sse/:connection_id", post(sse_send_handler))
+        .route("/poll", post(poll_open_handler))
+        .route(
+            "/poll/:connection_id",
+            get(poll_handler)
+                .post(poll_send_handler)
+                .delete(poll_close_handler),
+        )
+        .route("/apps/:app/http", any(http_root_handler))
+        .route("/apps/:app/http/*path", any(http_handler))
+        .route("/ready", get(ready_handler))
+        .route("/health", get(health_handler))
+        .with_state(state.clone());
+
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    match CONFIG.tls.as_ref() {
+        Some(tls) => {
+            let tls_config = load_server_config(tls, &[b"http/1.1"])?;
+
+            info!("ws server listening on wss://{}", &addr);
+
+            let handle = Handle::new();
+            let shutdown_handle = handle.clone();
+            tokio::spawn(async move {
+                drain_on_shutdown(state).await;
+                shutdown_handle.shutdown();
+            });
+
+            axum_server::bind_rustls(addr, RustlsConfig::from_config(tls_config))
+                .handle(handle)
+                .serve(app)
+                .await?;
+        }
+        None => {
+            info!("ws server listening on ws://{}", &addr);
+
+            axum::Server::bind(&addr)
+                .serve(app)
+                .with_graceful_shutdown(drain_on_shutdown(state))
+                .await?;
+        }
+    }
 
-    info!("ws server listening on ws://{}", &addr);
+    info!("ws server stopped");
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    registry.deregister_node(&CONFIG.node_id).await?;
 
     Ok(())
 }
 
 async fn ws_upgrade_handler(
     ws: WebSocketUpgrade,
-    query: Query<WsUpgradeQueryParams>,
+    query: Query<TransportQueryParams>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     State(state): State<WsState>,
-) -> impl IntoResponse {
+) -> Response {
+    let Some(app_name) = resolve_request_app(query.app.as_deref(), &headers) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    upgrade(ws, app_name, headers, raw_query, remote_addr, state).await
+}
+
+async fn ws_path_upgrade_handler(
+    ws: WebSocketUpgrade,
+    Path(app): Path<String>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    State(state): State<WsState>,
+) -> Response {
+    let Some(app_name) = resolve_request_app(Some(&app), &headers) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    upgrade(ws, app_name, headers, raw_query, remote_addr, state).await
+}
+
+async fn upgrade(
+    ws: WebSocketUpgrade,
+    app_name: String,
+    headers: HeaderMap,
+    raw_query: Option<String>,
+    remote_addr: SocketAddr,
+    state: WsState,
+) -> Response {
+    if state.draining.is_draining() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    let authorized = match authorize_connection(
+        app_name.clone(),
+        Transport::WebSocket,
+        &headers,
+        raw_query,
+        remote_addr,
+        state.bus.clone(),
+    )
+    .await
+    {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
     ws.on_upgrade(move |socket| async move {
-        let runtime_result = handle_socket(socket, query.app.clone(), state.bus).await;
+        let runtime_result = handle_socket(socket, app_name, authorized, state).await;
 
         if runtime_result.is_err() {
             error!("runtime error {:?}", runtime_result);
@@ -104,7 +234,17 @@ fn socket_tx_task(
     bus: Bus,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
-        while let Ok(msg) = bus.subscribe().recv().await {
+        let mut rx = bus.subscribe();
+        loop {
+            let msg = match rx.recv().await {
+                Ok(msg) => msg,
+                Err(RecvError::Lagged(skipped)) => {
+                    BUS_LAGGED_MESSAGES.inc_by(skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
             match msg {
                 BusMessage::TxWsMessage {
                     connection_id,
@@ -117,45 +257,87 @@ fn socket_tx_task(
                     let _ = socket_tx.send(message).await;
                 }
                 BusMessage::Kill { connection_id } => {
-                    if connection_id != connection_id {
+                    if connection_id != root_connection_id {
                         continue;
                     }
 
                     break;
                 }
+                BusMessage::Shutdown => {
+                    let _ = socket_tx
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server shutting down".into(),
+                        })))
+                        .await;
+
+                    break;
+                }
                 _ => {}
             };
         }
     })
 }
 
-async fn runtime_task(root_connection_id: String, bus: Bus, app_name: String) -> Result<()> {
-    let index = Index::load(CONFIG.storage_path.clone())?;
-    let Some(index_entry) = index
-        .entries()
-        .iter()
-        .find(|e| e.abi_header.name == app_name)
-    else {
-        bail!("app not found");
-    };
-    let app_path: PathBuf = CONFIG.storage_path.parse()?;
-    let app_path = app_path.join(index_entry.path.clone());
+/// Resolves the app a transport request targets: by name or alias when given, otherwise by `Host` header.
+/// Returns the app's canonical name.
+pub fn resolve_request_app(app: Option<&str>, headers: &HeaderMap) -> Option<String> {
+    let index = Index::load(CONFIG.storage_path.clone()).ok()?;
 
-    let runtime_env = RuntimeEnv::new(root_connection_id.clone(), bus.clone());
-    let mut runtime = Runtime::new(
+    let entry = match app {
+        Some(app) => index.find_app(app),
+        None => {
+            let host = headers.get(HOST)?.to_str().ok()?;
+            index.find_app_by_host(host)
+        }
+    }?;
+
+    Some(entry.abi_header.name.clone())
+}
+
+pub fn create_runtime(connection_id: String, bus: Bus, app_name: &str) -> Result<Runtime> {
+    let (index_entry, app_path) = resolve_app(app_name)?;
+
+    let runtime_env = RuntimeEnv::new(connection_id, bus);
+    let runtime = Runtime::new(
         index_entry.abi_header.name.clone(),
         app_path.to_str().unwrap().to_owned(),
         index_entry.abi_header.clone(),
         runtime_env,
     )?;
 
-    runtime.start()?;
+    Ok(runtime)
+}
+
+pub fn resolve_app(app_name: &str) -> Result<(IndexEntry, PathBuf)> {
+    let index = Index::load(CONFIG.storage_path.clone())?;
+    let Some(index_entry) = index.find_app(app_name) else {
+        bail!("app not found");
+    };
+    let app_path: PathBuf = CONFIG.storage_path.parse()?;
+    let app_path = app_path.join(index_entry.path.clone());
+
+    Ok((index_entry.clone(), app_path))
+}
 
+async fn runtime_task(root_connection_id: String, bus: Bus, mut runtime: Runtime) -> Result<()> {
+    runtime.init()?;
+
+    let mut rx = bus.subscribe();
     bus.clone().send(BusMessage::Ready {
         connection_id: root_connection_id.clone(),
     });
 
-    while let Ok(msg) = bus.subscribe().recv().await {
+    loop {
+        let msg = match rx.recv().await {
+            Ok(msg) => msg,
+            Err(RecvError::Lagged(skipped)) => {
+                BUS_LAGGED_MESSAGES.inc_by(skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
         match msg {
             BusMessage::Kill { connection_id } => {
                 if connection_id != root_connection_id {
@@ -164,6 +346,7 @@ async fn runtime_task(root_connection_id: String, bus: Bus, app_name: String) ->
 
                 break;
             }
+            BusMessage::Shutdown => break,
             BusMessage::RxWsMessage {
                 connection_id,
                 message,
@@ -178,18 +361,61 @@ async fn runtime_task(root_connection_id: String, bus: Bus, app_name: String) ->
                     _ => continue,
                 };
 
+                MESSAGES_RECEIVED
+                    .with_label_values(&[&runtime.app_name])
+                    .inc();
                 runtime.message(message)?;
             }
             BusMessage::CrossbarMessage(msg) => {
-                let content = match msg.content {
-                    unit_crossbar::CrossbarContent::Text(text) => CrossbarContent::Text(text),
-                    unit_crossbar::CrossbarContent::Binary(bin) => CrossbarContent::Binary(bin),
-                };
+                let user_id = presence::user_id(&root_connection_id);
+                if !msg
+                    .target
+                    .matches(&runtime.app_name, &root_connection_id, user_id.as_deref())
+                {
+                    continue;
+                }
+
+                let cx = start_span(
+                    "guest.topic",
+                    SpanKind::Consumer,
+                    &extract_context(&msg.trace_context),
+                    vec![
+                        KeyValue::new("crossbar.topic", msg.topic.clone()),
+                        KeyValue::new("unit.app", runtime.app_name.clone()),
+                        KeyValue::new("unit.connection_id", root_connection_id.clone()),
+                    ],
+                );
+
+                runtime.set_trace_id(trace_id(&cx));
+                let result = runtime.crossbar_event(to_guest_message(msg));
+                runtime.set_trace_id(None);
+
+                if let Err(e) = &result {
+                    cx.span().set_status(SpanStatus::error(e.to_string()));
+                }
+                result?;
+            }
+            BusMessage::CrossbarRequest(request) => {
+                let user_id = presence::user_id(&root_connection_id);
+                if !request.target.matches(
+                    &runtime.app_name,
+                    &root_connection_id,
+                    user_id.as_deref(),
+                ) {
+                    continue;
+                }
+
+                // app-wide requests reach every instance; only one answers
+                if !claim_request(&request).await {
+                    continue;
+                }
 
-                runtime.crossbar_event(CrossbarMessage {
-                    topic: msg.topic,
-                    content,
-                })?;
+                let result = match runtime.request(&request.name, request.payload.clone()) {
+                    Ok(Some(payload)) => CrossbarReplyResult::Ok(payload),
+                    Ok(None) => CrossbarReplyResult::NoHandler,
+                    Err(e) => CrossbarReplyResult::Failed(e.to_string()),
+                };
+                reply(&request, result);
             }
             _ => {}
         };
@@ -230,8 +456,80 @@ fn test_loop_ws_tx_task(connection_id: String, bus: Bus, ms: u64) {
     });
 }
 
-async fn handle_socket(socket: WebSocket, app_name: String, bus: Bus) -> Result<()> {
-    let connection_id = gen_uuid();
+/// Runs the app for a connection until it is killed, keeping it in the node's connection registry meanwhile.
+pub async fn run_connection(
+    app_name: String,
+    authorized: Authorized,
+    transport: Transport,
+    state: WsState,
+) -> Result<()> {
+    let connection_id = authorized.connection_id;
+
+    state.connections.insert(ConnectionInfo {
+        connection_id: connection_id.clone(),
+        app_name: app_name.clone(),
+        transport,
+    });
+    watch_app(&app_name).await;
+    presence::join(
+        &connection_id,
+        &app_name,
+        authorized.runtime.claims().as_deref(),
+    )
+    .await;
+
+    let runtime_result =
+        runtime_task(connection_id.clone(), state.bus.clone(), authorized.runtime).await;
+
+    state.connections.remove(&connection_id);
+    presence::leave(&connection_id).await;
+    unwatch_app(&app_name).await;
+
+    runtime_result
+}
+
+/// Starts the app for a connection whose transport is driven by plain HTTP requests (SSE,
+/// long-polling). Returns the session token the client's follow-up requests must carry.
+pub fn spawn_connection(
+    app_name: String,
+    authorized: Authorized,
+    transport: Transport,
+    state: WsState,
+) -> String {
+    let connection_id = authorized.connection_id.clone();
+    let session_token = state.session_tokens.issue(&connection_id);
+
+    // registered up front so the client can post as soon as it learns its connection id
+    state.connections.insert(ConnectionInfo {
+        connection_id: connection_id.clone(),
+        app_name: app_name.clone(),
+        transport,
+    });
+
+    tokio::spawn(async move {
+        let bus = state.bus.clone();
+        let session_tokens = state.session_tokens.clone();
+        let runtime_result = run_connection(app_name, authorized, transport, state).await;
+        session_tokens.revoke(&connection_id);
+
+        if runtime_result.is_err() {
+            error!("runtime error {:?}", runtime_result);
+
+            bus.send(BusMessage::Kill { connection_id });
+        }
+    });
+
+    session_token
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    app_name: String,
+    authorized: Authorized,
+    state: WsState,
+) -> Result<()> {
+    let bus = state.bus.clone();
+    let connection_id = authorized.connection_id.clone();
     let (socket_tx, socket_rx) = socket.split();
 
     let socket_rx_handle = socket_rx_task(connection_id.clone(), socket_rx, bus.clone());
@@ -240,7 +538,7 @@ async fn handle_socket(socket: WebSocket, app_name: String, bus: Bus) -> Result<
     // test_delayed_ready_task(connection_id.clone(), bus.clone(), 1_000);
     // test_loop_ws_tx_task(connection_id.clone(), bus.clone(), 3_000);
 
-    let runtime_result = runtime_task(connection_id.clone(), bus.clone(), app_name).await;
+    let runtime_result = run_connection(app_name, authorized, Transport::WebSocket, state).await;
     if runtime_result.is_err() {
         socket_rx_handle.abort();
         socket_tx_handle.abort();
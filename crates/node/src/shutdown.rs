@@ -0,0 +1,103 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode};
+use log::{info, warn};
+
+use unit_pubsub::PubSubHealth;
+
+use crate::{bus::BusMessage, config::CONFIG, crossbar::pubsub_health, server::WsState};
+
+/// Set once the node starts shutting down; new connections are refused from then on.
+#[derive(Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn start(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Resolves after a shutdown signal once every connection was closed (and its instance cleaned up),
+/// or the drain timeout passed.
+pub async fn drain_on_shutdown(state: WsState) {
+    shutdown_signal().await;
+
+    state.draining.start();
+    info!(
+        "shutting down, draining {} connections",
+        state.connections.len()
+    );
+
+    let drain = async {
+        while !state.connections.is_empty() {
+            // repeated so connections that were still starting up get it too
+            state.bus.send(BusMessage::Shutdown);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    };
+
+    let timeout = Duration::from_secs(CONFIG.drain_timeout_secs);
+    if tokio::time::timeout(timeout, drain).await.is_err() {
+        warn!(
+            "drain timeout, {} connections still open",
+            state.connections.len()
+        );
+    }
+}
+
+pub async fn ready_handler(State(state): State<WsState>) -> StatusCode {
+    if state.draining.is_draining() || pubsub_health() != PubSubHealth::Connected {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    StatusCode::OK
+}
+
+/// The pub/sub connection state; unavailable while it's reconnecting.
+pub async fn health_handler() -> (StatusCode, &'static str) {
+    let health = pubsub_health();
+    let status = match health {
+        PubSubHealth::Connected => StatusCode::OK,
+        PubSubHealth::Reconnecting => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, health.as_str())
+}
@@ -0,0 +1,127 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Path, Query, RawQuery, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures::{stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    auth::authorize_connection,
+    bus::BusMessage,
+    connections::Transport,
+    metrics::BUS_LAGGED_MESSAGES,
+    server::{resolve_request_app, spawn_connection, TransportQueryParams, WsState},
+    transport::{client_message, EncodedMessage, KillOnDrop},
+};
+
+fn message_event(message: EncodedMessage) -> Option<Event> {
+    Event::default().event("message").json_data(message).ok()
+}
+
+pub async fn sse_handler(
+    query: Query<TransportQueryParams>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    State(state): State<WsState>,
+) -> Response {
+    if state.draining.is_draining() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    let Some(app_name) = resolve_request_app(query.app.as_deref(), &headers) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let authorized = match authorize_connection(
+        app_name.clone(),
+        Transport::Sse,
+        &headers,
+        raw_query,
+        remote_addr,
+        state.bus.clone(),
+    )
+    .await
+    {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    let connection_id = authorized.connection_id.clone();
+    let rx = state.bus.subscribe();
+    let guard = KillOnDrop::new(connection_id.clone(), state.bus.clone());
+
+    let session_token = spawn_connection(app_name, authorized, Transport::Sse, state);
+
+    let connection_event = Event::default().event("connection").data(connection_id);
+    // posts to /sse/:connection_id carry it in the x-unit-session header
+    let session_event = Event::default().event("session").data(session_token);
+
+    let messages = stream::unfold((rx, guard), |(mut rx, mut guard)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(BusMessage::TxWsMessage {
+                    connection_id,
+                    message,
+                }) if connection_id == guard.connection_id => {
+                    let Some(event) = EncodedMessage::from_message(message).and_then(message_event)
+                    else {
+                        continue;
+                    };
+
+                    return Some((Ok::<_, Infallible>(event), (rx, guard)));
+                }
+                Ok(BusMessage::Kill { connection_id }) if connection_id == guard.connection_id => {
+                    guard.disarm();
+                    return None;
+                }
+                Ok(BusMessage::Shutdown) => {
+                    guard.disarm();
+                    return None;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    BUS_LAGGED_MESSAGES.inc_by(skipped);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter([Ok(connection_event), Ok(session_event)]).chain(messages);
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+pub async fn sse_send_handler(
+    Path(connection_id): Path<String>,
+    State(state): State<WsState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if !state.session_tokens.verify(&connection_id, &headers) {
+        return StatusCode::NOT_FOUND;
+    }
+
+    match state.connections.get(&connection_id) {
+        Some(info) if info.transport == Transport::Sse => {}
+        _ => return StatusCode::NOT_FOUND,
+    }
+
+    state.bus.send(BusMessage::RxWsMessage {
+        connection_id,
+        message: client_message(&headers, body),
+    });
+
+    StatusCode::ACCEPTED
+}
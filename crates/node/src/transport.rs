@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::ws::Message,
+    http::{header::CONTENT_TYPE, HeaderMap},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Serialize;
+use unit_utils::gen_uuid;
+
+use crate::bus::{Bus, BusMessage};
+
+/// Header carrying the session token on the requests that follow an SSE or long-polling open.
+pub static SESSION_TOKEN_HEADER: &str = "x-unit-session";
+
+/// Secrets handed only to the client that opened an SSE or long-polling connection. Connection
+/// ids can't serve as one: presence, `ListConnections` and push targets hand them out.
+#[derive(Clone, Default)]
+pub struct SessionTokens {
+    inner: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl SessionTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn issue(&self, connection_id: &str) -> String {
+        let token = gen_uuid();
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(connection_id.to_owned(), token.clone());
+
+        token
+    }
+
+    pub fn revoke(&self, connection_id: &str) {
+        self.inner.lock().unwrap().remove(connection_id);
+    }
+
+    /// Whether the request carries the token issued for `connection_id`.
+    pub fn verify(&self, connection_id: &str, headers: &HeaderMap) -> bool {
+        let Some(token) = headers
+            .get(SESSION_TOKEN_HEADER)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return false;
+        };
+
+        match self.inner.lock().unwrap().get(connection_id) {
+            Some(expected) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// JSON representation of a server → client message for the HTTP fallback transports.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum EncodedMessage {
+    Text(String),
+    Binary(String),
+}
+
+impl EncodedMessage {
+    pub fn from_message(message: Message) -> Option<Self> {
+        match message {
+            Message::Text(text) => Some(EncodedMessage::Text(text)),
+            Message::Binary(bin) => Some(EncodedMessage::Binary(BASE64.encode(bin))),
+            _ => None,
+        }
+    }
+}
+
+/// Turns a client → server HTTP body into the message the runtime would get from a socket.
+/// Bodies sent as `application/octet-stream` (or that aren't valid UTF-8) are binary.
+pub fn client_message(headers: &HeaderMap, body: Bytes) -> Message {
+    let is_binary = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/octet-stream"))
+        .unwrap_or(false);
+
+    if is_binary {
+        return Message::Binary(body.to_vec());
+    }
+
+    match String::from_utf8(body.to_vec()) {
+        Ok(text) => Message::Text(text),
+        Err(e) => Message::Binary(e.into_bytes()),
+    }
+}
+
+/// Kills the connection when an HTTP transport drops its side (e.g. the SSE client went away).
+pub struct KillOnDrop {
+    pub connection_id: String,
+    bus: Bus,
+    armed: bool,
+}
+
+impl KillOnDrop {
+    pub fn new(connection_id: String, bus: Bus) -> Self {
+        Self {
+            connection_id,
+            bus,
+            armed: true,
+        }
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        self.bus.send(BusMessage::Kill {
+            connection_id: self.connection_id.clone(),
+        });
+    }
+}
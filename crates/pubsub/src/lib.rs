@@ -1,39 +1,111 @@
-use fred::{
-    prelude::{ClientLike, RedisClient},
-    types::{Builder, RedisConfig},
-};
-use unit_utils::{shared_config::ConfigRedis, Result};
+use std::{collections::HashMap, sync::Arc};
 
-pub use fred::prelude::{PubsubInterface, RedisValue};
+use async_trait::async_trait;
+use fred::prelude::RedisClient;
+use tokio::sync::broadcast;
+use unit_utils::{shared_config::ConfigPubSub, Result};
 
-pub struct PubSub {
-    pub subscriber: RedisClient,
-    pub publisher: RedisClient,
+pub mod memory;
+pub mod nats;
+pub mod nodes;
+pub mod presence;
+pub mod redis;
+pub mod streams;
+
+/// Messages buffered per connection before slow receivers start lagging.
+const MESSAGE_BUFFER: usize = 1024;
+
+/// A message received on one of the connection's subscriptions.
+#[derive(Debug, Clone)]
+pub struct PubSubMessage {
+    pub channel: String,
+    pub payload: Vec<u8>,
+}
+
+/// What became of a publish that went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishOutcome {
+    Sent,
+    /// Buffered while the backend is unreachable; it goes out, in order, once it's back.
+    Queued,
+}
+
+/// Whether a connection can currently reach its backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PubSubHealth {
+    Connected,
+    /// Lost; subscriptions are restored once it's back.
+    Reconnecting,
 }
 
-impl PubSub {
-    pub async fn connect(config: ConfigRedis) -> Result<PubSub> {
-        let config = RedisConfig::from_url(&config.to_url())?;
+impl PubSubHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PubSubHealth::Connected => "connected",
+            PubSubHealth::Reconnecting => "reconnecting",
+        }
+    }
+}
+
+/// A connection to the pub/sub backend. Channels are `:`-separated names; prefix
+/// subscriptions should end at a `:` so every backend can express them.
+#[async_trait]
+pub trait PubSub: Send + Sync {
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<PublishOutcome>;
+
+    async fn subscribe(&self, channel: &str) -> Result<()>;
+
+    async fn unsubscribe(&self, channel: &str) -> Result<()>;
+
+    /// Receives every channel starting with `prefix`.
+    async fn subscribe_prefix(&self, prefix: &str) -> Result<()>;
 
-        let subscriber = Builder::from_config(config.clone()).build()?;
-        let publisher = subscriber.clone_new();
+    async fn unsubscribe_prefix(&self, prefix: &str) -> Result<()>;
 
-        subscriber.connect();
-        publisher.connect();
+    /// Messages of every subscription made on this connection.
+    fn on_message(&self) -> broadcast::Receiver<PubSubMessage>;
 
-        subscriber.wait_for_connect().await?;
-        publisher.wait_for_connect().await?;
+    /// Sets `key` unless it exists, so exactly one caller across the cluster gets `true`.
+    async fn claim(&self, key: &str, ttl_ms: u64) -> Result<bool> {
+        Ok(self.claim_or_get(key, "1", ttl_ms).await?.is_none())
+    }
+
+    /// Like `claim`, but the key holds `value`; a caller that loses gets the winner's value.
+    async fn claim_or_get(&self, key: &str, value: &str, ttl_ms: u64) -> Result<Option<String>>;
+
+    /// Replaces the value of a claim, held or not, and restarts its TTL.
+    async fn set_claim(&self, key: &str, value: &str, ttl_ms: u64) -> Result<()>;
+
+    /// Drops a claim so the key can be claimed again.
+    async fn unclaim(&self, key: &str) -> Result<()>;
+
+    async fn hash_set(&self, key: &str, field: &str, value: String) -> Result<()>;
+
+    async fn hash_remove(&self, key: &str, field: &str) -> Result<()>;
 
-        Ok(PubSub {
-            subscriber,
-            publisher,
-        })
+    async fn hash_entries(&self, key: &str) -> Result<HashMap<String, String>>;
+
+    /// Drops the whole hash once `ttl_ms` passes; calling it again pushes the expiry back.
+    async fn hash_expire(&self, key: &str, ttl_ms: u64) -> Result<()>;
+
+    fn health(&self) -> PubSubHealth {
+        PubSubHealth::Connected
     }
 
-    pub async fn publish(&self, topic: &str, message: Vec<u8>) -> Result<()> {
-        self.publisher
-            .publish(topic, RedisValue::Bytes(message.into()))
-            .await?;
-        Ok(())
+    /// The underlying client of the Redis backend; durable streams need it.
+    fn redis(&self) -> Option<&RedisClient> {
+        None
     }
 }
+
+pub type SharedPubSub = Arc<dyn PubSub>;
+
+pub async fn connect(config: &ConfigPubSub) -> Result<SharedPubSub> {
+    let pubsub: SharedPubSub = match config {
+        ConfigPubSub::Redis(config) => Arc::new(redis::RedisPubSub::connect(config).await?),
+        ConfigPubSub::Memory => Arc::new(memory::MemoryHub::global().connect()),
+        ConfigPubSub::Nats(config) => Arc::new(nats::NatsPubSub::connect(config).await?),
+    };
+
+    Ok(pubsub)
+}
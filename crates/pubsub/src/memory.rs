@@ -0,0 +1,370 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, OnceLock, Weak},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+use unit_utils::Result;
+
+use crate::{PubSub, PubSubMessage, PublishOutcome, MESSAGE_BUFFER};
+
+#[derive(Default)]
+struct Subscriptions {
+    channels: HashSet<String>,
+    prefixes: HashSet<String>,
+}
+
+impl Subscriptions {
+    fn matches(&self, channel: &str) -> bool {
+        self.channels.contains(channel)
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| channel.starts_with(prefix.as_str()))
+    }
+}
+
+#[derive(Default)]
+struct MemoryHash {
+    fields: HashMap<String, String>,
+    expires_at: Option<Instant>,
+}
+
+struct Connection {
+    subscriptions: Mutex<Subscriptions>,
+    messages: broadcast::Sender<PubSubMessage>,
+}
+
+/// Routes messages between the connections of one process; for single-process setups
+/// and tests.
+#[derive(Default)]
+pub struct MemoryHub {
+    connections: Mutex<Vec<Weak<Connection>>>,
+    // value and expiry of each claim
+    claims: Mutex<HashMap<String, (String, Instant)>>,
+    hashes: Mutex<HashMap<String, MemoryHash>>,
+}
+
+static GLOBAL_HUB: OnceLock<Arc<MemoryHub>> = OnceLock::new();
+
+impl MemoryHub {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// The hub shared by every in-memory connection of this process.
+    pub fn global() -> Arc<Self> {
+        GLOBAL_HUB.get_or_init(MemoryHub::new).clone()
+    }
+
+    // the hashes, without the ones that expired
+    fn live_hashes(&self) -> MutexGuard<'_, HashMap<String, MemoryHash>> {
+        let now = Instant::now();
+        let mut hashes = self.hashes.lock().unwrap();
+        hashes.retain(|_, hash| !matches!(hash.expires_at, Some(expires_at) if expires_at <= now));
+
+        hashes
+    }
+
+    pub fn connect(self: &Arc<Self>) -> MemoryPubSub {
+        let (messages, _) = broadcast::channel(MESSAGE_BUFFER);
+        let connection = Arc::new(Connection {
+            subscriptions: Mutex::new(Subscriptions::default()),
+            messages,
+        });
+
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|connection| connection.strong_count() > 0);
+        connections.push(Arc::downgrade(&connection));
+
+        MemoryPubSub {
+            hub: self.clone(),
+            connection,
+        }
+    }
+}
+
+pub struct MemoryPubSub {
+    hub: Arc<MemoryHub>,
+    connection: Arc<Connection>,
+}
+
+#[async_trait]
+impl PubSub for MemoryPubSub {
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<PublishOutcome> {
+        let connections: Vec<Arc<Connection>> = self
+            .hub
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|connection| connection.upgrade())
+            .collect();
+
+        for connection in connections {
+            if !connection.subscriptions.lock().unwrap().matches(channel) {
+                continue;
+            }
+
+            let _ = connection.messages.send(PubSubMessage {
+                channel: channel.to_owned(),
+                payload: payload.clone(),
+            });
+        }
+
+        Ok(PublishOutcome::Sent)
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<()> {
+        let mut subscriptions = self.connection.subscriptions.lock().unwrap();
+        subscriptions.channels.insert(channel.to_owned());
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, channel: &str) -> Result<()> {
+        let mut subscriptions = self.connection.subscriptions.lock().unwrap();
+        subscriptions.channels.remove(channel);
+        Ok(())
+    }
+
+    async fn subscribe_prefix(&self, prefix: &str) -> Result<()> {
+        let mut subscriptions = self.connection.subscriptions.lock().unwrap();
+        subscriptions.prefixes.insert(prefix.to_owned());
+        Ok(())
+    }
+
+    async fn unsubscribe_prefix(&self, prefix: &str) -> Result<()> {
+        let mut subscriptions = self.connection.subscriptions.lock().unwrap();
+        subscriptions.prefixes.remove(prefix);
+        Ok(())
+    }
+
+    fn on_message(&self) -> broadcast::Receiver<PubSubMessage> {
+        self.connection.messages.subscribe()
+    }
+
+    async fn claim_or_get(&self, key: &str, value: &str, ttl_ms: u64) -> Result<Option<String>> {
+        let now = Instant::now();
+        let mut claims = self.hub.claims.lock().unwrap();
+        claims.retain(|_, (_, expires_at)| *expires_at > now);
+
+        if let Some((existing, _)) = claims.get(key) {
+            return Ok(Some(existing.clone()));
+        }
+
+        let expires_at = now + Duration::from_millis(ttl_ms);
+        claims.insert(key.to_owned(), (value.to_owned(), expires_at));
+        Ok(None)
+    }
+
+    async fn set_claim(&self, key: &str, value: &str, ttl_ms: u64) -> Result<()> {
+        let expires_at = Instant::now() + Duration::from_millis(ttl_ms);
+        self.hub
+            .claims
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), (value.to_owned(), expires_at));
+        Ok(())
+    }
+
+    async fn unclaim(&self, key: &str) -> Result<()> {
+        self.hub.claims.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn hash_set(&self, key: &str, field: &str, value: String) -> Result<()> {
+        let mut hashes = self.hub.live_hashes();
+        hashes
+            .entry(key.to_owned())
+            .or_default()
+            .fields
+            .insert(field.to_owned(), value);
+        Ok(())
+    }
+
+    async fn hash_remove(&self, key: &str, field: &str) -> Result<()> {
+        let mut hashes = self.hub.live_hashes();
+        if let Some(hash) = hashes.get_mut(key) {
+            hash.fields.remove(field);
+
+            // like Redis, an empty hash doesn't exist, and neither does its expiry
+            if hash.fields.is_empty() {
+                hashes.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    async fn hash_entries(&self, key: &str) -> Result<HashMap<String, String>> {
+        let hashes = self.hub.live_hashes();
+        Ok(hashes
+            .get(key)
+            .map(|hash| hash.fields.clone())
+            .unwrap_or_default())
+    }
+
+    async fn hash_expire(&self, key: &str, ttl_ms: u64) -> Result<()> {
+        let mut hashes = self.hub.live_hashes();
+        if let Some(hash) = hashes.get_mut(key) {
+            hash.expires_at = Some(Instant::now() + Duration::from_millis(ttl_ms));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn sleep_ms(ms: u64) {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+    }
+
+    #[tokio::test]
+    async fn publish_reaches_subscribers() {
+        let hub = MemoryHub::new();
+        let publisher = hub.connect();
+        let subscriber = hub.connect();
+        let mut messages = subscriber.on_message();
+
+        subscriber.subscribe("crossbar").await.unwrap();
+        subscriber
+            .subscribe_prefix("crossbar:app:a:")
+            .await
+            .unwrap();
+
+        publisher
+            .publish("crossbar:app:a:t", vec![1])
+            .await
+            .unwrap();
+        publisher
+            .publish("crossbar:app:b:t", vec![2])
+            .await
+            .unwrap();
+        publisher.publish("crossbar", vec![3]).await.unwrap();
+
+        let message = messages.recv().await.unwrap();
+        assert_eq!(message.channel, "crossbar:app:a:t");
+        assert_eq!(message.payload, vec![1]);
+        assert_eq!(messages.recv().await.unwrap().payload, vec![3]);
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_stops_delivery() {
+        let hub = MemoryHub::new();
+        let connection = hub.connect();
+        let mut messages = connection.on_message();
+
+        connection.subscribe("a").await.unwrap();
+        connection.subscribe_prefix("b:").await.unwrap();
+        connection.unsubscribe("a").await.unwrap();
+        connection.unsubscribe_prefix("b:").await.unwrap();
+
+        connection.publish("a", vec![1]).await.unwrap();
+        connection.publish("b:c", vec![2]).await.unwrap();
+
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn hubs_are_isolated() {
+        let subscriber = MemoryHub::new().connect();
+        let mut messages = subscriber.on_message();
+        subscriber.subscribe("a").await.unwrap();
+
+        MemoryHub::new()
+            .connect()
+            .publish("a", vec![1])
+            .await
+            .unwrap();
+
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn claims() {
+        let hub = MemoryHub::new();
+        let a = hub.connect();
+        let b = hub.connect();
+
+        assert_eq!(a.claim_or_get("k", "a", 10_000).await.unwrap(), None);
+        assert_eq!(
+            b.claim_or_get("k", "b", 10_000).await.unwrap(),
+            Some("a".to_owned())
+        );
+        assert!(!b.claim("k", 10_000).await.unwrap());
+
+        a.set_claim("k", "c", 10_000).await.unwrap();
+        assert_eq!(
+            b.claim_or_get("k", "b", 10_000).await.unwrap(),
+            Some("c".to_owned())
+        );
+
+        a.unclaim("k").await.unwrap();
+        assert!(b.claim("k", 10_000).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn claims_expire() {
+        let connection = MemoryHub::new().connect();
+
+        assert!(connection.claim("k", 20).await.unwrap());
+        assert!(!connection.claim("k", 20).await.unwrap());
+
+        sleep_ms(40).await;
+        assert!(connection.claim("k", 20).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn hashes() {
+        let hub = MemoryHub::new();
+        let a = hub.connect();
+        let b = hub.connect();
+
+        a.hash_set("h", "f1", "v1".to_owned()).await.unwrap();
+        a.hash_set("h", "f2", "v2".to_owned()).await.unwrap();
+        a.hash_set("h", "f1", "v3".to_owned()).await.unwrap();
+
+        let entries = b.hash_entries("h").await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["f1"], "v3");
+        assert_eq!(entries["f2"], "v2");
+
+        b.hash_remove("h", "f1").await.unwrap();
+        assert_eq!(a.hash_entries("h").await.unwrap().len(), 1);
+        assert!(a.hash_entries("other").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hashes_expire() {
+        let connection = MemoryHub::new().connect();
+
+        connection.hash_set("h", "f", "v".to_owned()).await.unwrap();
+        connection.hash_expire("h", 20).await.unwrap();
+        assert_eq!(connection.hash_entries("h").await.unwrap().len(), 1);
+
+        sleep_ms(40).await;
+        assert!(connection.hash_entries("h").await.unwrap().is_empty());
+
+        // a new hash under the same key starts without an expiry
+        connection.hash_set("h", "f", "v".to_owned()).await.unwrap();
+        sleep_ms(40).await;
+        assert_eq!(connection.hash_entries("h").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn emptied_hashes_lose_their_expiry() {
+        let connection = MemoryHub::new().connect();
+
+        connection.hash_set("h", "f", "v".to_owned()).await.unwrap();
+        connection.hash_expire("h", 20).await.unwrap();
+        connection.hash_remove("h", "f").await.unwrap();
+        connection.hash_set("h", "f", "v".to_owned()).await.unwrap();
+
+        sleep_ms(40).await;
+        assert_eq!(connection.hash_entries("h").await.unwrap().len(), 1);
+    }
+}
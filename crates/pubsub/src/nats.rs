@@ -0,0 +1,533 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_nats::{
+    connection::State,
+    jetstream::{
+        self,
+        consumer::{push, DeliverPolicy, ReplayPolicy},
+        kv,
+    },
+    Client, HeaderMap,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::{
+    sync::{broadcast, OnceCell},
+    task::JoinHandle,
+};
+use unit_utils::{
+    err::{anyhow::anyhow, bail},
+    shared_config::ConfigNats,
+    Result,
+};
+
+use crate::{PubSub, PubSubHealth, PubSubMessage, PublishOutcome, MESSAGE_BUFFER};
+
+/// Carries the original channel, which subjects can't represent exactly.
+static CHANNEL_HEADER: &str = "Unit-Channel";
+
+/// Set by the server on the deletes and purges of a KV bucket.
+static KV_OPERATION_HEADER: &str = "KV-Operation";
+
+static CLAIMS_BUCKET: &str = "unit_claims";
+static HASHES_BUCKET: &str = "unit_hashes";
+
+/// JetStream buckets have no per-key TTL, so every claim carries its own expiry and an expired
+/// claim is taken over like a released one. The bucket's max age only clears out old claims,
+/// which also makes it the longest TTL a claim can have.
+const CLAIMS_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Writing a claim only fails when another one landed first, which the next read sees.
+const CLAIM_ATTEMPTS: usize = 3;
+
+/// Backend for NATS; claims and hashes need JetStream enabled on the server. The client
+/// reconnects and restores subscriptions by itself.
+pub struct NatsPubSub {
+    client: Client,
+    messages: broadcast::Sender<PubSubMessage>,
+    // forwarding task of each subscription, by subject
+    subscriptions: Mutex<HashMap<String, JoinHandle<()>>>,
+    claims: OnceCell<kv::Store>,
+    hashes: OnceCell<kv::Store>,
+}
+
+fn channel_subject(channel: &str) -> Result<String> {
+    if channel.is_empty() || channel.contains(|c: char| c.is_whitespace() || c == '*' || c == '>') {
+        bail!("{:?} can't be used as a NATS subject", channel);
+    }
+
+    Ok(channel.replace(':', "."))
+}
+
+fn prefix_subject(prefix: &str) -> Result<String> {
+    let Some(prefix) = prefix.strip_suffix(':') else {
+        bail!(
+            "NATS prefix subscriptions must end at a `:`, got {:?}",
+            prefix
+        );
+    };
+
+    Ok(format!("{}.>", channel_subject(prefix)?))
+}
+
+// KV keys only allow a few characters, so names are hex encoded
+fn hex(value: &str) -> String {
+    value.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(value: &str) -> Option<String> {
+    let bytes = (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+fn hash_field_key(key: &str, field: &str) -> String {
+    format!("{}.{}", hex(key), hex(field))
+}
+
+// the fields live under `{hex(key)}.`, so this can't collide with one of them
+fn hash_expiry_key(key: &str) -> String {
+    hex(key)
+}
+
+fn hash_expired(expiry: &[u8], now: u64) -> bool {
+    String::from_utf8_lossy(expiry)
+        .parse::<u64>()
+        .is_ok_and(|expires_at| expires_at <= now)
+}
+
+fn encode_claim(value: &str, expires_at: u64) -> String {
+    format!("{} {}", expires_at, value)
+}
+
+// the value of the claim in `entry`, unless it was released or expired
+fn held_claim(entry: &kv::Entry, now: u64) -> Option<String> {
+    if !matches!(entry.operation, kv::Operation::Put) {
+        return None;
+    }
+
+    let (expires_at, value) = std::str::from_utf8(&entry.value).ok()?.split_once(' ')?;
+    if expires_at.parse::<u64>().ok()? <= now {
+        return None;
+    }
+
+    Some(value.to_owned())
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl NatsPubSub {
+    pub async fn connect(config: &ConfigNats) -> Result<NatsPubSub> {
+        let client = async_nats::connect(config.url.as_str()).await?;
+        let (messages, _) = broadcast::channel(MESSAGE_BUFFER);
+
+        Ok(NatsPubSub {
+            client,
+            messages,
+            subscriptions: Mutex::new(HashMap::new()),
+            claims: OnceCell::new(),
+            hashes: OnceCell::new(),
+        })
+    }
+
+    async fn add_subscription(&self, subject: String) -> Result<()> {
+        if self.subscriptions.lock().unwrap().contains_key(&subject) {
+            return Ok(());
+        }
+
+        let mut subscriber = self.client.subscribe(subject.clone()).await?;
+        let tx = self.messages.clone();
+        let task = tokio::spawn(async move {
+            while let Some(message) = subscriber.next().await {
+                let channel = message
+                    .headers
+                    .as_ref()
+                    .and_then(|headers| headers.get(CHANNEL_HEADER))
+                    .map(|channel| channel.to_string())
+                    .unwrap_or_else(|| message.subject.replace('.', ":"));
+
+                let _ = tx.send(PubSubMessage {
+                    channel,
+                    payload: message.payload.to_vec(),
+                });
+            }
+        });
+
+        // a concurrent subscribe to the same subject may have won; keep only one
+        if let Some(previous) = self.subscriptions.lock().unwrap().insert(subject, task) {
+            previous.abort();
+        }
+
+        Ok(())
+    }
+
+    // dropping the subscriber with the task unsubscribes
+    fn remove_subscription(&self, subject: &str) {
+        if let Some(task) = self.subscriptions.lock().unwrap().remove(subject) {
+            task.abort();
+        }
+    }
+
+    /// Latest value of every key starting with `prefix`, read through a consumer filtered to
+    /// them instead of listing the whole bucket.
+    async fn entries_with_prefix(
+        &self,
+        store: &kv::Store,
+        prefix: &str,
+    ) -> Result<HashMap<String, String>> {
+        let consumer = store
+            .stream
+            .create_consumer(push::OrderedConfig {
+                deliver_subject: self.client.new_inbox(),
+                filter_subject: format!("{}{}>", store.prefix, prefix),
+                deliver_policy: DeliverPolicy::LastPerSubject,
+                replay_policy: ReplayPolicy::Instant,
+                ..Default::default()
+            })
+            .await?;
+
+        let mut entries = HashMap::new();
+        if consumer.cached_info().num_pending == 0 {
+            return Ok(entries);
+        }
+
+        let mut messages = consumer.messages().await?;
+        while let Some(message) = messages.next().await {
+            let message = message?;
+            let pending = message.info().map_err(|e| anyhow!(e))?.pending;
+
+            let operation = message
+                .headers
+                .as_ref()
+                .and_then(|headers| headers.get(KV_OPERATION_HEADER))
+                .and_then(|operation| operation.as_str().parse().ok())
+                .unwrap_or(kv::Operation::Put);
+
+            if let Some(kv_key) = message.subject.strip_prefix(store.prefix.as_str()) {
+                match operation {
+                    kv::Operation::Put => {
+                        let value = String::from_utf8_lossy(&message.payload).into_owned();
+                        entries.insert(kv_key.to_owned(), value);
+                    }
+                    _ => {
+                        entries.remove(kv_key);
+                    }
+                }
+            }
+
+            if pending == 0 {
+                break;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Deletes the hash under `key` once its expiry has passed, as Redis would have by then.
+    /// Returns whether it did.
+    async fn clear_expired_hash(&self, store: &kv::Store, key: &str) -> Result<bool> {
+        let expired = match store.get(hash_expiry_key(key)).await? {
+            Some(expiry) => hash_expired(&expiry, unix_millis()),
+            None => false,
+        };
+        if !expired {
+            return Ok(false);
+        }
+
+        let prefix = format!("{}.", hex(key));
+        for kv_key in self.entries_with_prefix(store, &prefix).await?.keys() {
+            store.delete(kv_key).await?;
+        }
+        store.delete(hash_expiry_key(key)).await?;
+
+        Ok(true)
+    }
+
+    async fn store<'a>(
+        &'a self,
+        cell: &'a OnceCell<kv::Store>,
+        bucket: &str,
+        max_age: Duration,
+    ) -> Result<&'a kv::Store> {
+        let store = cell
+            .get_or_try_init(|| async {
+                let context = jetstream::new(self.client.clone());
+                if let Ok(store) = context.get_key_value(bucket).await {
+                    return Ok(store);
+                }
+
+                let store = context
+                    .create_key_value(kv::Config {
+                        bucket: bucket.to_owned(),
+                        history: 1,
+                        max_age,
+                        ..Default::default()
+                    })
+                    .await?;
+
+                Ok::<_, unit_utils::err::anyhow::Error>(store)
+            })
+            .await?;
+
+        Ok(store)
+    }
+}
+
+impl Drop for NatsPubSub {
+    fn drop(&mut self) {
+        for (_, task) in self.subscriptions.lock().unwrap().drain() {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl PubSub for NatsPubSub {
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<PublishOutcome> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CHANNEL_HEADER, channel);
+
+        self.client
+            .publish_with_headers(channel_subject(channel)?, headers, payload.into())
+            .await?;
+        Ok(PublishOutcome::Sent)
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<()> {
+        self.add_subscription(channel_subject(channel)?).await
+    }
+
+    async fn unsubscribe(&self, channel: &str) -> Result<()> {
+        self.remove_subscription(&channel_subject(channel)?);
+        Ok(())
+    }
+
+    async fn subscribe_prefix(&self, prefix: &str) -> Result<()> {
+        self.add_subscription(prefix_subject(prefix)?).await
+    }
+
+    async fn unsubscribe_prefix(&self, prefix: &str) -> Result<()> {
+        self.remove_subscription(&prefix_subject(prefix)?);
+        Ok(())
+    }
+
+    fn on_message(&self) -> broadcast::Receiver<PubSubMessage> {
+        self.messages.subscribe()
+    }
+
+    fn health(&self) -> PubSubHealth {
+        match self.client.connection_state() {
+            State::Connected => PubSubHealth::Connected,
+            _ => PubSubHealth::Reconnecting,
+        }
+    }
+
+    async fn claim_or_get(&self, key: &str, value: &str, ttl_ms: u64) -> Result<Option<String>> {
+        if u128::from(ttl_ms) > CLAIMS_MAX_AGE.as_millis() {
+            bail!("NATS claims can't be held longer than {:?}", CLAIMS_MAX_AGE);
+        }
+
+        let store = self
+            .store(&self.claims, CLAIMS_BUCKET, CLAIMS_MAX_AGE)
+            .await?;
+        let key = hex(key);
+
+        for _ in 0..CLAIM_ATTEMPTS {
+            let now = unix_millis();
+
+            // revision 0 only succeeds if the key doesn't exist yet; a released or expired
+            // claim is written over by its revision
+            let revision = match store.entry(key.as_str()).await? {
+                Some(entry) => {
+                    if let Some(existing) = held_claim(&entry, now) {
+                        return Ok(Some(existing));
+                    }
+                    entry.revision
+                }
+                None => 0,
+            };
+
+            let claim = encode_claim(value, now.saturating_add(ttl_ms));
+            if store.update(&key, claim.into(), revision).await.is_ok() {
+                return Ok(None);
+            }
+        }
+
+        bail!("Failed to claim {} in {} attempts", key, CLAIM_ATTEMPTS)
+    }
+
+    async fn set_claim(&self, key: &str, value: &str, ttl_ms: u64) -> Result<()> {
+        if u128::from(ttl_ms) > CLAIMS_MAX_AGE.as_millis() {
+            bail!("NATS claims can't be held longer than {:?}", CLAIMS_MAX_AGE);
+        }
+
+        let store = self
+            .store(&self.claims, CLAIMS_BUCKET, CLAIMS_MAX_AGE)
+            .await?;
+        let claim = encode_claim(value, unix_millis().saturating_add(ttl_ms));
+        store.put(hex(key), claim.into()).await?;
+        Ok(())
+    }
+
+    async fn unclaim(&self, key: &str) -> Result<()> {
+        let store = self
+            .store(&self.claims, CLAIMS_BUCKET, CLAIMS_MAX_AGE)
+            .await?;
+        store.purge(hex(key)).await?;
+        Ok(())
+    }
+
+    async fn hash_set(&self, key: &str, field: &str, value: String) -> Result<()> {
+        let store = self
+            .store(&self.hashes, HASHES_BUCKET, Duration::ZERO)
+            .await?;
+        // a hash recreated after it expired starts empty and without an expiry
+        self.clear_expired_hash(store, key).await?;
+        store.put(hash_field_key(key, field), value.into()).await?;
+        Ok(())
+    }
+
+    async fn hash_remove(&self, key: &str, field: &str) -> Result<()> {
+        let store = self
+            .store(&self.hashes, HASHES_BUCKET, Duration::ZERO)
+            .await?;
+        store.delete(hash_field_key(key, field)).await?;
+
+        // an emptied hash is gone in Redis, expiry included
+        let prefix = format!("{}.", hex(key));
+        if self.entries_with_prefix(store, &prefix).await?.is_empty() {
+            store.delete(hash_expiry_key(key)).await?;
+        }
+        Ok(())
+    }
+
+    async fn hash_entries(&self, key: &str) -> Result<HashMap<String, String>> {
+        let store = self
+            .store(&self.hashes, HASHES_BUCKET, Duration::ZERO)
+            .await?;
+        if self.clear_expired_hash(store, key).await? {
+            return Ok(HashMap::new());
+        }
+
+        let prefix = format!("{}.", hex(key));
+        let entries = self
+            .entries_with_prefix(store, &prefix)
+            .await?
+            .into_iter()
+            .filter_map(|(kv_key, value)| Some((unhex(&kv_key[prefix.len()..])?, value)))
+            .collect();
+
+        Ok(entries)
+    }
+
+    // JetStream buckets have no per-key TTL, so the expiry is kept in a key of its own and
+    // enforced when the hash is read
+    async fn hash_expire(&self, key: &str, ttl_ms: u64) -> Result<()> {
+        let store = self
+            .store(&self.hashes, HASHES_BUCKET, Duration::ZERO)
+            .await?;
+        let expires_at = unix_millis().saturating_add(ttl_ms);
+        store
+            .put(hash_expiry_key(key), expires_at.to_string().into())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: &str, operation: kv::Operation) -> kv::Entry {
+        kv::Entry {
+            bucket: CLAIMS_BUCKET.to_owned(),
+            key: hex("claim"),
+            value: value.to_owned().into(),
+            revision: 1,
+            delta: 0,
+            created: time::OffsetDateTime::UNIX_EPOCH,
+            operation,
+        }
+    }
+
+    #[test]
+    fn channel_subjects() {
+        assert_eq!(channel_subject("crossbar").unwrap(), "crossbar");
+        assert_eq!(
+            channel_subject("crossbar:app:chat").unwrap(),
+            "crossbar.app.chat"
+        );
+
+        for channel in ["", "a b", "a*", "a>", "a\tb"] {
+            assert!(channel_subject(channel).is_err(), "{:?}", channel);
+        }
+    }
+
+    #[test]
+    fn prefix_subjects() {
+        assert_eq!(prefix_subject("crossbar:app:").unwrap(), "crossbar.app.>");
+
+        for prefix in ["crossbar:app", "", ":", "a*:"] {
+            assert!(prefix_subject(prefix).is_err(), "{:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        for value in ["", "key", "a.b:c d", "caf\u{e9}"] {
+            let encoded = hex(value);
+            assert!(
+                encoded.bytes().all(|b| b.is_ascii_hexdigit()),
+                "{}",
+                encoded
+            );
+            assert_eq!(unhex(&encoded).as_deref(), Some(value));
+        }
+
+        assert_eq!(hex("a.b"), "612e62");
+        assert_eq!(unhex("6"), None);
+        assert_eq!(unhex("zz"), None);
+        assert_eq!(unhex("ff"), None);
+    }
+
+    #[test]
+    fn hash_keys() {
+        assert_eq!(hash_field_key("h", "f"), "68.66");
+        // fields are the only keys with a `.`
+        assert_eq!(hash_expiry_key("h.f"), "682e66");
+    }
+
+    #[test]
+    fn hash_expiries() {
+        assert!(hash_expired(b"100", 100));
+        assert!(hash_expired(b"100", 101));
+        assert!(!hash_expired(b"100", 99));
+        assert!(!hash_expired(b"never", 101));
+    }
+
+    #[test]
+    fn held_claims() {
+        let claim = encode_claim("message id", 100);
+
+        assert_eq!(
+            held_claim(&entry(&claim, kv::Operation::Put), 99).as_deref(),
+            Some("message id")
+        );
+        assert_eq!(held_claim(&entry(&claim, kv::Operation::Put), 100), None);
+        assert_eq!(held_claim(&entry(&claim, kv::Operation::Delete), 99), None);
+        assert_eq!(held_claim(&entry(&claim, kv::Operation::Purge), 99), None);
+        assert_eq!(held_claim(&entry("garbage", kv::Operation::Put), 99), None);
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use unit_utils::Result;
+
+use crate::PubSub;
+
+static NODES_KEY: &str = "unit:nodes";
+
+/// What a node last reported about itself.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NodeInfo {
+    pub id: String,
+    pub address: String,
+    pub version: String,
+    pub started_at: u64,
+    pub last_heartbeat: u64,
+    /// The node is considered gone once this passes without a new heartbeat.
+    pub expires_at: u64,
+    pub draining: bool,
+    /// Active connections per app.
+    pub connections: HashMap<String, u64>,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl dyn PubSub {
+    pub async fn register_node(&self, node: &NodeInfo) -> Result<()> {
+        let value = serde_json::to_string(node)?;
+
+        self.hash_set(NODES_KEY, &node.id, value).await
+    }
+
+    pub async fn deregister_node(&self, node_id: &str) -> Result<()> {
+        self.hash_remove(NODES_KEY, node_id).await
+    }
+
+    /// Lists live nodes, pruning the ones whose heartbeat expired.
+    pub async fn list_nodes(&self) -> Result<Vec<NodeInfo>> {
+        let entries = self.hash_entries(NODES_KEY).await?;
+
+        let now = unix_now();
+        let mut nodes = vec![];
+
+        for (id, value) in entries {
+            match serde_json::from_str::<NodeInfo>(&value) {
+                Ok(node) if node.expires_at >= now => nodes.push(node),
+                _ => self.deregister_node(&id).await?,
+            }
+        }
+
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(nodes)
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use unit_utils::Result;
+
+use crate::{nodes::unix_now, PubSub};
+
+fn presence_key(app_name: &str) -> String {
+    format!("unit:presence:{}", app_name)
+}
+
+/// A connection as seen by the rest of the cluster.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PresenceRecord {
+    pub connection_id: String,
+    pub app_name: String,
+    pub node_id: String,
+    pub user_id: Option<String>,
+    pub room: Option<String>,
+    pub metadata: Option<String>,
+    pub joined_at: u64,
+    /// Refreshed with the owning node's heartbeat; stale records are ignored and pruned.
+    pub expires_at: u64,
+}
+
+impl dyn PubSub {
+    pub async fn set_presence(&self, record: &PresenceRecord) -> Result<()> {
+        let key = presence_key(&record.app_name);
+        let value = serde_json::to_string(record)?;
+
+        self.hash_set(&key, &record.connection_id, value).await?;
+
+        // records are refreshed with every heartbeat; once no node of the app is left to do
+        // that, the hash goes away instead of lingering until the next list
+        let ttl_secs = record.expires_at.saturating_sub(unix_now()).max(1);
+        self.hash_expire(&key, ttl_secs * 1000).await
+    }
+
+    pub async fn remove_presence(&self, app_name: &str, connection_id: &str) -> Result<()> {
+        self.hash_remove(&presence_key(app_name), connection_id)
+            .await
+    }
+
+    pub async fn list_presence(&self, app_name: &str) -> Result<Vec<PresenceRecord>> {
+        let entries = self.hash_entries(&presence_key(app_name)).await?;
+
+        let now = unix_now();
+        let mut records = vec![];
+
+        for (connection_id, value) in entries {
+            match serde_json::from_str::<PresenceRecord>(&value) {
+                Ok(record) if record.expires_at >= now => records.push(record),
+                _ => self.remove_presence(app_name, &connection_id).await?,
+            }
+        }
+
+        records.sort_by_key(|record| record.joined_at);
+
+        Ok(records)
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use fred::{
+    clients::SubscriberClient,
+    interfaces::{EventInterface, HashesInterface, KeysInterface},
+    prelude::{ClientLike, PubsubInterface, RedisClient, RedisValue},
+    types::{Builder, Expiration, ReconnectPolicy, RedisConfig, SetOptions, TlsConnector},
+};
+use log::{error, info, warn};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+};
+use unit_tls::load_client_config;
+use unit_utils::{err::bail, gen_uuid, shared_config::ConfigRedis, Result};
+
+use crate::{PubSub, PubSubHealth, PubSubMessage, PublishOutcome, MESSAGE_BUFFER};
+
+/// How often buffered publishes are retried while Redis keeps failing them.
+const FLUSH_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the startup check waits for its message to come back.
+const PATTERN_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Backend for Redis. Both connections reconnect with backoff, and the subscriber restores its
+/// subscriptions afterwards.
+pub struct RedisPubSub {
+    subscriber: SubscriberClient,
+    publisher: RedisClient,
+    messages: broadcast::Sender<PubSubMessage>,
+    pending: Arc<PendingPublishes>,
+}
+
+/// Publishes made while the publisher was disconnected, sent in order once it's back. Only the
+/// flush task sends them, so they can't overtake each other.
+struct PendingPublishes {
+    limit: usize,
+    queue: Mutex<VecDeque<(String, Vec<u8>)>>,
+    /// Wakes the flush task when a publish is buffered.
+    pushed: Notify,
+}
+
+impl PendingPublishes {
+    fn push(&self, channel: &str, payload: Vec<u8>) -> Result<()> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= self.limit {
+                bail!("Redis is unavailable and the publish buffer is full");
+            }
+
+            queue.push_back((channel.to_owned(), payload));
+        }
+
+        self.pushed.notify_one();
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
+    // a publish stays at the front until it went out, so publishers keep queueing behind it
+    async fn flush(&self, publisher: &RedisClient) {
+        loop {
+            let Some((channel, payload)) = self.queue.lock().unwrap().front().cloned() else {
+                return;
+            };
+
+            let value = RedisValue::Bytes(payload.into());
+            if let Err(e) = publisher.publish::<(), _, _>(channel.as_str(), value).await {
+                warn!("failed to flush buffered publishes {:?}", e);
+                return;
+            }
+
+            self.queue.lock().unwrap().pop_front();
+        }
+    }
+}
+
+fn escape_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('*');
+
+    pattern
+}
+
+/// The fred config for `config`; everything talking to Redis should connect with it.
+pub fn redis_config(config: &ConfigRedis) -> Result<RedisConfig> {
+    let mut redis_config = RedisConfig::from_url(&config.url)?;
+
+    if config.username.is_some() {
+        redis_config.username = config.username.clone();
+    }
+    if config.password.is_some() {
+        redis_config.password = config.password.clone();
+    }
+
+    if let Some(ca_path) = config.tls_ca_path.as_deref() {
+        if !config.url.starts_with("rediss") {
+            bail!("A Redis TLS CA is configured but the URL doesn't use TLS");
+        }
+
+        let connector = TlsConnector::from(load_client_config(ca_path)?);
+        redis_config.tls = Some(connector.into());
+    }
+
+    Ok(redis_config)
+}
+
+impl RedisPubSub {
+    pub async fn connect(config: &ConfigRedis) -> Result<RedisPubSub> {
+        let policy = ReconnectPolicy::new_exponential(
+            0,
+            config.reconnect_min_ms,
+            config.reconnect_max_ms,
+            2,
+        );
+        let publish_buffer = config.publish_buffer;
+        let config = redis_config(config)?;
+
+        let mut builder = Builder::from_config(config);
+        builder.set_policy(policy);
+
+        let subscriber = builder.build_subscriber_client()?;
+        let publisher = builder.build()?;
+
+        subscriber.connect();
+        publisher.connect();
+
+        subscriber.wait_for_connect().await?;
+        publisher.wait_for_connect().await?;
+
+        // resubscribes to every channel and pattern after a reconnect
+        subscriber.manage_subscriptions();
+
+        check_pattern_subscriptions(&subscriber, &publisher).await?;
+
+        let pending = Arc::new(PendingPublishes {
+            limit: publish_buffer,
+            queue: Mutex::new(VecDeque::new()),
+            pushed: Notify::new(),
+        });
+        start_flush_task(&publisher, pending.clone());
+        start_error_task(&subscriber);
+
+        let (messages, _) = broadcast::channel(MESSAGE_BUFFER);
+        let mut rx = subscriber.on_message();
+        let tx = messages.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = match rx.recv().await {
+                    Ok(message) => message,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("dropped {} redis messages", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let payload = match message.value {
+                    RedisValue::String(text) => text.as_bytes().to_vec(),
+                    RedisValue::Bytes(bytes) => bytes.to_vec(),
+                    _ => continue,
+                };
+
+                let _ = tx.send(PubSubMessage {
+                    channel: message.channel.to_string(),
+                    payload,
+                });
+            }
+        });
+
+        Ok(RedisPubSub {
+            subscriber,
+            publisher,
+            messages,
+            pending,
+        })
+    }
+}
+
+/// Prefix subscriptions are pattern subscriptions, which a deployment can fail to deliver while
+/// plain ones work (a Cluster proxy, or an ACL that only allows some channels), so one is checked
+/// end to end before anything relies on them.
+async fn check_pattern_subscriptions(
+    subscriber: &SubscriberClient,
+    publisher: &RedisClient,
+) -> Result<()> {
+    let prefix = format!("unit:check:{}:", gen_uuid());
+    let pattern = escape_pattern(&prefix);
+    let channel = format!("{}pattern", prefix);
+    let mut messages = subscriber.on_message();
+
+    subscriber.psubscribe(pattern.as_str()).await?;
+    publisher
+        .publish::<(), _, _>(channel.as_str(), "check")
+        .await?;
+
+    let received = tokio::time::timeout(PATTERN_CHECK_TIMEOUT, async {
+        loop {
+            match messages.recv().await {
+                Ok(message) if message.channel.to_string() == channel => return true,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return false,
+            }
+        }
+    })
+    .await
+    .unwrap_or(false);
+
+    subscriber.punsubscribe(pattern.as_str()).await?;
+
+    if !received {
+        bail!(
+            "Redis didn't deliver a message to a pattern subscription within {:?}",
+            PATTERN_CHECK_TIMEOUT
+        );
+    }
+
+    Ok(())
+}
+
+// flushes buffered publishes on reconnect, when one is buffered while connected, and on a
+// timer for as long as Redis keeps failing them
+fn start_flush_task(publisher: &RedisClient, pending: Arc<PendingPublishes>) {
+    let mut reconnects = publisher.on_reconnect();
+    let publisher = publisher.clone();
+    tokio::spawn(async move {
+        let mut retry = tokio::time::interval(FLUSH_RETRY_INTERVAL);
+
+        loop {
+            tokio::select! {
+                reconnect = reconnects.recv() => match reconnect {
+                    Ok(_) | Err(RecvError::Lagged(_)) => info!("reconnected to redis"),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = pending.pushed.notified() => {}
+                _ = retry.tick() => {}
+            }
+
+            if publisher.is_connected() && !pending.is_empty() {
+                pending.flush(&publisher).await;
+            }
+        }
+    });
+}
+
+fn start_error_task(subscriber: &SubscriberClient) {
+    let mut errors = subscriber.on_error();
+    tokio::spawn(async move {
+        loop {
+            match errors.recv().await {
+                Ok(e) => error!("redis connection error {:?}", e),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[async_trait]
+impl PubSub for RedisPubSub {
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<PublishOutcome> {
+        if self.pending.limit == 0 {
+            self.publisher
+                .publish::<(), _, _>(channel, RedisValue::Bytes(payload.into()))
+                .await?;
+            return Ok(PublishOutcome::Sent);
+        }
+
+        // queued behind earlier buffered publishes so they keep their order
+        if !self.publisher.is_connected() || !self.pending.is_empty() {
+            self.pending.push(channel, payload)?;
+            return Ok(PublishOutcome::Queued);
+        }
+
+        let value = RedisValue::Bytes(payload.clone().into());
+        if let Err(e) = self.publisher.publish::<(), _, _>(channel, value).await {
+            if self.publisher.is_connected() {
+                return Err(e.into());
+            }
+            self.pending.push(channel, payload)?;
+            return Ok(PublishOutcome::Queued);
+        }
+
+        Ok(PublishOutcome::Sent)
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<()> {
+        self.subscriber.subscribe(channel).await?;
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, channel: &str) -> Result<()> {
+        self.subscriber.unsubscribe(channel).await?;
+        Ok(())
+    }
+
+    async fn subscribe_prefix(&self, prefix: &str) -> Result<()> {
+        self.subscriber.psubscribe(escape_pattern(prefix)).await?;
+        Ok(())
+    }
+
+    async fn unsubscribe_prefix(&self, prefix: &str) -> Result<()> {
+        self.subscriber.punsubscribe(escape_pattern(prefix)).await?;
+        Ok(())
+    }
+
+    fn on_message(&self) -> broadcast::Receiver<PubSubMessage> {
+        self.messages.subscribe()
+    }
+
+    fn health(&self) -> PubSubHealth {
+        if self.subscriber.is_connected() && self.publisher.is_connected() {
+            PubSubHealth::Connected
+        } else {
+            PubSubHealth::Reconnecting
+        }
+    }
+
+    async fn claim_or_get(&self, key: &str, value: &str, ttl_ms: u64) -> Result<Option<String>> {
+        let ttl_ms = i64::try_from(ttl_ms)?;
+
+        // a claim that expired or was released between the SET and the GET is free again
+        loop {
+            let claimed: Option<String> = self
+                .publisher
+                .set(
+                    key,
+                    value,
+                    Some(Expiration::PX(ttl_ms)),
+                    Some(SetOptions::NX),
+                    false,
+                )
+                .await?;
+
+            if claimed.is_some() {
+                return Ok(None);
+            }
+
+            let existing: Option<String> = self.publisher.get(key).await?;
+            if existing.is_some() {
+                return Ok(existing);
+            }
+        }
+    }
+
+    async fn set_claim(&self, key: &str, value: &str, ttl_ms: u64) -> Result<()> {
+        self.publisher
+            .set::<(), _, _>(
+                key,
+                value,
+                Some(Expiration::PX(i64::try_from(ttl_ms)?)),
+                None,
+                false,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn unclaim(&self, key: &str) -> Result<()> {
+        self.publisher.del::<(), _>(key).await?;
+        Ok(())
+    }
+
+    async fn hash_set(&self, key: &str, field: &str, value: String) -> Result<()> {
+        self.publisher.hset::<(), _, _>(key, (field, value)).await?;
+        Ok(())
+    }
+
+    async fn hash_remove(&self, key: &str, field: &str) -> Result<()> {
+        self.publisher.hdel::<(), _, _>(key, field).await?;
+        Ok(())
+    }
+
+    async fn hash_entries(&self, key: &str) -> Result<HashMap<String, String>> {
+        let entries = self
+            .publisher
+            .hgetall::<HashMap<String, String>, _>(key)
+            .await?;
+        Ok(entries)
+    }
+
+    async fn hash_expire(&self, key: &str, ttl_ms: u64) -> Result<()> {
+        self.publisher
+            .pexpire::<(), _>(key, i64::try_from(ttl_ms)?)
+            .await?;
+        Ok(())
+    }
+
+    fn redis(&self) -> Option<&RedisClient> {
+        Some(&self.publisher)
+    }
+}
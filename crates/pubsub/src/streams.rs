@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use fred::{
+    interfaces::{HashesInterface, StreamsInterface},
+    prelude::{ClientLike, RedisClient, RedisValue},
+    types::XReadResponse,
+};
+use unit_utils::{err::bail, Result};
+
+use crate::PubSub;
+
+static MESSAGE_FIELD: &str = "message";
+
+/// An entry of a crossbar stream: its id and the encoded message.
+pub type StreamEntry = (String, Vec<u8>);
+
+fn stream_offsets_key(node_id: &str) -> String {
+    format!("unit:stream-offsets:{}", node_id)
+}
+
+fn entry_message(fields: HashMap<String, RedisValue>) -> Option<Vec<u8>> {
+    match fields.get(MESSAGE_FIELD)? {
+        RedisValue::String(text) => Some(text.as_bytes().to_vec()),
+        RedisValue::Bytes(bytes) => Some(bytes.to_vec()),
+        _ => None,
+    }
+}
+
+/// Stream ids are `{unix ms}-{sequence}`, which don't sort as strings.
+pub fn parse_stream_id(id: &str) -> (u64, u64) {
+    let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+    (ms.parse().unwrap_or(0), seq.parse().unwrap_or(0))
+}
+
+impl dyn PubSub {
+    fn stream_client(&self) -> Result<&RedisClient> {
+        let Some(client) = self.redis() else {
+            bail!("Durable topics need the Redis backend");
+        };
+
+        Ok(client)
+    }
+
+    /// Appends an encoded message to a stream, trimming it to roughly `max_len` entries.
+    pub async fn append(&self, stream: &str, message: Vec<u8>, max_len: u64) -> Result<String> {
+        let id: String = self
+            .stream_client()?
+            .xadd(
+                stream,
+                false,
+                ("MAXLEN", "~", max_len as i64),
+                "*",
+                (MESSAGE_FIELD, RedisValue::Bytes(message.into())),
+            )
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Entries from `start` (a stream id, `(id` to exclude it) onwards, oldest first.
+    pub async fn read_range(
+        &self,
+        stream: &str,
+        start: &str,
+        count: u64,
+    ) -> Result<Vec<StreamEntry>> {
+        let entries: Vec<(String, HashMap<String, RedisValue>)> = self
+            .stream_client()?
+            .xrange(stream, start, "+", Some(count))
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(id, fields)| Some((id, entry_message(fields)?)))
+            .collect())
+    }
+
+    /// Id of the newest entry, or `0-0` for an empty stream.
+    pub async fn last_stream_id(&self, stream: &str) -> Result<String> {
+        let entries: Vec<(String, HashMap<String, RedisValue>)> = self
+            .stream_client()?
+            .xrevrange(stream, "+", "-", Some(1))
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .next()
+            .map(|(id, _)| id)
+            .unwrap_or_else(|| "0-0".to_owned()))
+    }
+
+    pub async fn load_stream_offset(&self, node_id: &str, stream: &str) -> Result<Option<String>> {
+        let offset = self
+            .stream_client()?
+            .hget::<Option<String>, _, _>(stream_offsets_key(node_id), stream)
+            .await?;
+
+        Ok(offset)
+    }
+
+    pub async fn save_stream_offset(&self, node_id: &str, stream: &str, id: &str) -> Result<()> {
+        self.stream_client()?
+            .hset::<(), _, _>(stream_offsets_key(node_id), (stream, id))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_stream_offset(&self, node_id: &str, stream: &str) -> Result<()> {
+        self.stream_client()?
+            .hdel::<(), _, _>(stream_offsets_key(node_id), stream)
+            .await?;
+
+        Ok(())
+    }
+
+    /// A separate connection for blocking stream reads, which would otherwise hold up
+    /// every other command on the shared one.
+    pub async fn stream_reader(&self) -> Result<StreamReader> {
+        let client = self.stream_client()?.clone_new();
+        client.connect();
+        client.wait_for_connect().await?;
+
+        Ok(StreamReader { client })
+    }
+}
+
+pub struct StreamReader {
+    client: RedisClient,
+}
+
+impl StreamReader {
+    /// Waits up to `block_ms` for entries after the given id of each stream.
+    pub async fn read(
+        &self,
+        streams: &[(String, String)],
+        block_ms: u64,
+        count: u64,
+    ) -> Result<HashMap<String, Vec<StreamEntry>>> {
+        let keys: Vec<&str> = streams.iter().map(|(key, _)| key.as_str()).collect();
+        let ids: Vec<&str> = streams.iter().map(|(_, id)| id.as_str()).collect();
+
+        let response: XReadResponse<String, String, String, RedisValue> = self
+            .client
+            .xread_map(Some(count), Some(block_ms), keys, ids)
+            .await?;
+
+        Ok(response
+            .into_iter()
+            .map(|(stream, entries)| {
+                let entries = entries
+                    .into_iter()
+                    .filter_map(|(id, fields)| Some((id, entry_message(fields)?)))
+                    .collect();
+
+                (stream, entries)
+            })
+            .collect())
+    }
+}
@@ -49,6 +49,263 @@ impl CrossbarContent {
 pub struct CrossbarMessage {
     pub topic: String,
     pub content: CrossbarContent,
+    /// Stream id of durable messages; replay after it to resume from this message.
+    pub stream_id: Option<String>,
+    /// Assigned by the host when the message is published.
+    pub message_id: String,
+    /// Publish time in unix milliseconds; assigned by the host.
+    pub timestamp_ms: u64,
+    /// `api`, `app:<name>` or `node:<id>`; assigned by the host.
+    pub publisher: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+    /// Undelivered copies are dropped once the message is this old.
+    pub ttl_ms: Option<u64>,
+    /// Segments captured by the topic pattern of the handler receiving the message.
+    pub params: TopicParams,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TopicParams(pub Vec<(String, String)>);
+
+impl TopicParams {
+    /// A `{name}` capture, or a `*`/`**` capture by its segment index.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl CrossbarMessage {
+    pub fn new(topic: String, content: CrossbarContent) -> Self {
+        Self {
+            topic,
+            content,
+            stream_id: None,
+            message_id: String::new(),
+            timestamp_ms: 0,
+            publisher: None,
+            content_type: None,
+            headers: vec![],
+            ttl_ms: None,
+            params: TopicParams::default(),
+        }
+    }
+
+    pub fn text(topic: String, text: String) -> Self {
+        Self::new(topic, CrossbarContent::Text(text))
+    }
+
+    pub fn binary(topic: String, bytes: Vec<u8>) -> Self {
+        Self::new(topic, CrossbarContent::Binary(bytes))
+    }
+
+    pub fn with_content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_owned());
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn with_ttl_ms(mut self, ttl_ms: u64) -> Self {
+        self.ttl_ms = Some(ttl_ms);
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Where a replay of durable crossbar messages starts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ReplayFrom {
+    /// Messages after this stream id.
+    AfterId(String),
+    /// Messages published at or after this unix time, in milliseconds.
+    Since(u64),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplayRequest {
+    pub from: ReplayFrom,
+    /// Empty replays every durable topic.
+    pub topics: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn text(status: u16, body: String) -> Self {
+        Self::new(status)
+            .with_header("content-type", "text/plain; charset=utf-8")
+            .with_body(body.into_bytes())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConnectionInfo {
+    pub connection_id: String,
+    pub app_name: String,
+    pub transport: Option<String>,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub remote_addr: Option<String>,
+    /// Claims (JSON) attached to the connection during authorization.
+    pub claims: Option<String>,
+}
+
+impl ConnectionInfo {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        let query = self.query.as_ref()?;
+
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.header("user-agent")
+    }
+}
+
+/// Crossbar topics the node publishes to when connections come and go (content is a JSON `Presence`).
+pub static PRESENCE_JOIN_TOPIC: &str = "presence-join";
+pub static PRESENCE_LEAVE_TOPIC: &str = "presence-leave";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Presence {
+    pub connection_id: String,
+    pub app_name: String,
+    pub node_id: String,
+    pub user_id: Option<String>,
+    pub room: Option<String>,
+    pub metadata: Option<String>,
+    pub joined_at: u64,
+}
+
+impl Presence {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Option<Self> {
+        serde_json::from_str(json).ok()
+    }
+}
+
+/// Changes to the current connection's presence; `None` fields are left as they are.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PresenceUpdate {
+    pub user_id: Option<String>,
+    pub room: Option<String>,
+    pub metadata: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuthorizeRequest {
+    pub headers: Vec<(String, String)>,
+    pub query: Option<String>,
+    pub origin: Option<String>,
+    pub remote_addr: Option<String>,
+    /// Claims (JSON) of a bearer token the node already verified.
+    pub claims: Option<String>,
+}
+
+impl AuthorizeRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuthorizeResponse {
+    pub accept: bool,
+    pub reason: Option<String>,
+    /// Claims attached to the connection when accepted.
+    pub claims: Option<String>,
+}
+
+impl AuthorizeResponse {
+    pub fn accept() -> Self {
+        Self {
+            accept: true,
+            reason: None,
+            claims: None,
+        }
+    }
+
+    pub fn reject(reason: &str) -> Self {
+        Self {
+            accept: false,
+            reason: Some(reason.to_owned()),
+            claims: None,
+        }
+    }
+
+    pub fn with_claims(mut self, claims: String) -> Self {
+        self.claims = Some(claims);
+        self
+    }
 }
 
 pub fn encode_runtime_proto_message<T>(message: &T) -> Result<Vec<u8>>
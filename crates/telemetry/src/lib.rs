@@ -0,0 +1,107 @@
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::{SpanKind, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use unit_utils::Result;
+
+pub use opentelemetry;
+
+/// W3C trace context (`traceparent`/`tracestate`) as carried in crossbar envelopes.
+pub type TraceCarrier = Vec<(String, String)>;
+
+struct CarrierInjector<'a>(&'a mut TraceCarrier);
+
+impl<'a> Injector for CarrierInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.push((key.to_owned(), value));
+    }
+}
+
+struct CarrierExtractor<'a>(&'a [(String, String)]);
+
+impl<'a> Extractor for CarrierExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(k, _)| k.as_str()).collect()
+    }
+}
+
+/// Sets up W3C trace context propagation and, when an endpoint is given, exports spans via OTLP/gRPC.
+/// Without an endpoint spans are not recorded but trace context is still passed along.
+pub fn init_tracing(service_name: &'static str, otlp_endpoint: Option<&str>) -> Result<()> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let Some(otlp_endpoint) = otlp_endpoint else {
+        return Ok(());
+    };
+
+    let resource = Resource::new(vec![KeyValue::new("service.name", service_name)]);
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(otlp_endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(resource))
+        .install_batch(runtime::Tokio)?;
+
+    Ok(())
+}
+
+/// Flushes pending spans.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+pub fn inject_context(cx: &Context) -> TraceCarrier {
+    let mut carrier = vec![];
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(cx, &mut CarrierInjector(&mut carrier))
+    });
+
+    carrier
+}
+
+pub fn extract_context(carrier: &[(String, String)]) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&CarrierExtractor(carrier)))
+}
+
+/// Starts a span under `parent` and returns the context holding it; the span ends when that context is dropped.
+pub fn start_span(
+    name: &'static str,
+    kind: SpanKind,
+    parent: &Context,
+    attributes: Vec<KeyValue>,
+) -> Context {
+    let tracer = global::tracer("unit");
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+
+    parent.with_span(span)
+}
+
+pub fn trace_id(cx: &Context) -> Option<String> {
+    let span = cx.span();
+    let span_context = span.span_context();
+
+    if !span_context.is_valid() {
+        return None;
+    }
+
+    Some(span_context.trace_id().to_string())
+}
@@ -0,0 +1,166 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use log::{error, info};
+use rustls::{
+    server::{AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, RootCertStore,
+};
+use rustls_pemfile::Item;
+use unit_utils::{
+    err::{bail, Context},
+    shared_config::ConfigTls,
+    Result,
+};
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+fn read_pem(path: &str) -> Result<Vec<Item>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse {}", path))?;
+
+    Ok(items)
+}
+
+fn read_certs(path: &str) -> Result<Vec<Certificate>> {
+    let certs: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path);
+    }
+
+    Ok(certs)
+}
+
+fn read_private_key(path: &str) -> Result<PrivateKey> {
+    let key = read_pem(path)?.into_iter().find_map(|item| match item {
+        Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+        _ => None,
+    });
+
+    let Some(key) = key else {
+        bail!("No private key found in {}", path);
+    };
+
+    Ok(key)
+}
+
+fn load_certified_key(config: &ConfigTls) -> Result<CertifiedKey> {
+    let certs = read_certs(&config.cert_path)?;
+    let key = read_private_key(&config.key_path)?;
+
+    let Ok(key) = sign::any_supported_type(&key) else {
+        bail!("Unsupported private key type in {}", config.key_path);
+    };
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Serves the current certificate; swapped in place when the files on disk change.
+struct ReloadingCertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn modified_at(config: &ConfigTls) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    (modified(&config.cert_path), modified(&config.key_path))
+}
+
+fn start_reload_task(config: ConfigTls, resolver: Arc<ReloadingCertResolver>) {
+    if config.reload_interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.reload_interval_secs));
+        let mut last_modified = modified_at(&config);
+
+        loop {
+            interval.tick().await;
+
+            let modified = modified_at(&config);
+            if modified == last_modified {
+                continue;
+            }
+
+            // on failure (e.g. cert written but key not yet) we retry on the next tick
+            match load_certified_key(&config) {
+                Ok(key) => {
+                    *resolver.current.write().unwrap() = Arc::new(key);
+                    last_modified = modified;
+                    info!("reloaded tls certificate from {}", config.cert_path);
+                }
+                Err(e) => error!("failed to reload tls certificate {:?}", e),
+            }
+        }
+    });
+}
+
+/// A rustls client config that trusts only the CAs in `ca_path`.
+pub fn load_client_config(ca_path: &str) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca_path)? {
+        roots.add(&cert)?;
+    }
+
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(client_config)
+}
+
+/// Builds a rustls server config from the configured PEM files and keeps the certificate
+/// fresh by polling them for changes. Must be called from within a tokio runtime.
+pub fn load_server_config(
+    config: &ConfigTls,
+    alpn_protocols: &[&[u8]],
+) -> Result<Arc<ServerConfig>> {
+    let resolver = Arc::new(ReloadingCertResolver {
+        current: RwLock::new(Arc::new(load_certified_key(config)?)),
+    });
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+
+    let mut server_config = match config.client_ca_path.as_ref() {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca_path)? {
+                roots.add(&cert)?;
+            }
+
+            builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+                .with_cert_resolver(resolver.clone())
+        }
+        None => builder
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone()),
+    };
+
+    server_config.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+
+    start_reload_task(config.clone(), resolver);
+
+    Ok(Arc::new(server_config))
+}
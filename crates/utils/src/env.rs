@@ -54,3 +54,14 @@ pub fn str_or_default(key: &str, default: &str) -> String {
         Err(_) => default.to_string(),
     }
 }
+
+pub fn list_or_empty(key: &str) -> Vec<String> {
+    match std::env::var(key) {
+        Ok(value) => value
+            .split(',')
+            .map(|item| item.trim().to_owned())
+            .filter(|item| !item.is_empty())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
@@ -3,25 +3,62 @@ use std::{fs, path};
 
 #[derive(Clone, Debug)]
 pub struct ConfigRedis {
-    pub host: String,
-    pub port: u32,
-    pub auth: Option<String>,
-    pub db: u32,
+    /// Any URL fred accepts, including Sentinel and Cluster ones.
+    pub url: String,
+    /// ACL credentials; these override any in the URL.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// CA certificates to trust instead of the system ones, for `rediss://` URLs.
+    pub tls_ca_path: Option<String>,
+    /// Reconnect delays double from `reconnect_min_ms` up to `reconnect_max_ms`.
+    pub reconnect_min_ms: u32,
+    pub reconnect_max_ms: u32,
+    /// Publishes held while disconnected and sent on reconnect; 0 fails them instead.
+    pub publish_buffer: usize,
 }
 
-impl ConfigRedis {
-    pub fn to_url(&self) -> String {
-        if self.auth.is_some() {
-            format!(
-                "redis://{}@{}:{}/{}",
-                self.auth.clone().unwrap(),
-                self.host,
-                self.port,
-                self.db
-            )
-        } else {
-            format!("redis://{}:{}/{}", self.host, self.port, self.db)
-        }
+#[derive(Clone, Debug)]
+pub struct ConfigNats {
+    pub url: String,
+}
+
+/// Where crossbar messages, claims and cluster state go.
+#[derive(Clone, Debug)]
+pub enum ConfigPubSub {
+    Redis(ConfigRedis),
+    /// In-process only: nothing is shared with other processes.
+    Memory,
+    Nats(ConfigNats),
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigTls {
+    pub cert_path: String,
+    pub key_path: String,
+    /// When set, clients must present a certificate signed by one of these CAs.
+    pub client_ca_path: Option<String>,
+    /// 0 disables reloading.
+    pub reload_interval_secs: u64,
+}
+
+/// Crossbar topics backed by Redis Streams instead of PUBLISH.
+#[derive(Clone, Debug)]
+pub struct ConfigDurable {
+    /// Topic names, or `*` for every topic. Empty disables durable delivery.
+    pub topics: Vec<String>,
+    /// Approximate number of entries kept per stream.
+    pub max_len: u64,
+    /// Most messages a single replay returns.
+    pub replay_limit: u64,
+}
+
+impl ConfigDurable {
+    pub fn is_enabled(&self) -> bool {
+        !self.topics.is_empty()
+    }
+
+    pub fn is_durable(&self, topic: &str) -> bool {
+        self.topics.iter().any(|t| t == "*" || t == topic)
     }
 }
 
@@ -46,20 +83,92 @@ pub fn resolve_storage_path() -> String {
     return storage_path.to_str().unwrap().to_owned();
 }
 
+/// `UNIT_REDIS_URL` is `redis://` or `rediss://` (TLS) for a single server,
+/// `redis-sentinel://host:26379,host2:26379/db?sentinelServiceName=name` for Sentinel or
+/// `redis-cluster://host:6379,host2:6379` for Cluster. Without it the URL is built from
+/// `UNIT_REDIS_HOST`, `UNIT_REDIS_PORT`, `UNIT_REDIS_DB` and `UNIT_REDIS_TLS`.
 pub fn resolve_redis() -> Option<ConfigRedis> {
-    match env::optional_str("UNIT_REDIS_HOST") {
-        Some(host) => {
+    let url = match env::optional_str("UNIT_REDIS_URL") {
+        Some(url) => url,
+        None => {
+            let host = env::optional_str("UNIT_REDIS_HOST")?;
             let port = env::value_or_default("UNIT_REDIS_PORT", 6379u32);
-            let auth = env::optional_str("UNIT_REDIS_AUTH");
             let db = env::value_or_default("UNIT_REDIS_DB", 3u32);
+            let scheme = match env::value_or_default("UNIT_REDIS_TLS", false) {
+                true => "rediss",
+                false => "redis",
+            };
+
+            format!("{}://{}:{}/{}", scheme, host, port, db)
+        }
+    };
+
+    // UNIT_REDIS_AUTH is the older name of the password
+    let password =
+        env::optional_str("UNIT_REDIS_PASSWORD").or_else(|| env::optional_str("UNIT_REDIS_AUTH"));
+
+    Some(ConfigRedis {
+        url,
+        username: env::optional_str("UNIT_REDIS_USERNAME"),
+        password,
+        tls_ca_path: env::optional_str("UNIT_REDIS_TLS_CA"),
+        reconnect_min_ms: env::value_or_default("UNIT_REDIS_RECONNECT_MIN_MS", 100u32),
+        reconnect_max_ms: env::value_or_default("UNIT_REDIS_RECONNECT_MAX_MS", 10000u32),
+        publish_buffer: env::value_or_default("UNIT_REDIS_PUBLISH_BUFFER", 0usize),
+    })
+}
+
+/// `UNIT_PUBSUB_BACKEND` is `redis`, `memory` or `nats`; unset, Redis is used when
+/// `UNIT_REDIS_URL` or `UNIT_REDIS_HOST` is set. The in-memory backend shares nothing with
+/// other processes, so it also needs `UNIT_SINGLE_PROCESS=true`.
+pub fn resolve_pubsub() -> ConfigPubSub {
+    let backend = env::optional_str("UNIT_PUBSUB_BACKEND");
+
+    match (backend.as_deref(), resolve_redis()) {
+        (None, Some(redis)) | (Some("redis"), Some(redis)) => ConfigPubSub::Redis(redis),
+        (Some("redis"), None) => panic!("UNIT_REDIS_URL or UNIT_REDIS_HOST must be set"),
+        (None, None) => {
+            panic!("UNIT_REDIS_URL, UNIT_REDIS_HOST or UNIT_PUBSUB_BACKEND must be set")
+        }
+        (Some("memory"), _) => {
+            if !env::value_or_default("UNIT_SINGLE_PROCESS", false) {
+                panic!("The in-memory pub/sub backend needs UNIT_SINGLE_PROCESS=true");
+            }
+            ConfigPubSub::Memory
+        }
+        (Some("nats"), _) => ConfigPubSub::Nats(ConfigNats {
+            url: env::str_or_default("UNIT_NATS_URL", "nats://127.0.0.1:4222"),
+        }),
+        (Some(backend), _) => panic!("{} is not a valid value for UNIT_PUBSUB_BACKEND", backend),
+    }
+}
+
+/// Reads `{prefix}_TLS_CERT`, `{prefix}_TLS_KEY` and `{prefix}_TLS_CLIENT_CA` (e.g. `UNIT_WS_TLS_CERT`).
+pub fn resolve_tls(prefix: &str) -> Option<ConfigTls> {
+    let cert_key = format!("{}_TLS_CERT", prefix);
+    let key_key = format!("{}_TLS_KEY", prefix);
 
-            Some(ConfigRedis {
-                host,
-                port,
-                auth,
-                db,
+    match (env::optional_str(&cert_key), env::optional_str(&key_key)) {
+        (Some(cert_path), Some(key_path)) => {
+            let client_ca_path = env::optional_str(&format!("{}_TLS_CLIENT_CA", prefix));
+            let reload_interval_secs = env::value_or_default("UNIT_TLS_RELOAD_SECS", 30u64);
+
+            Some(ConfigTls {
+                cert_path,
+                key_path,
+                client_ca_path,
+                reload_interval_secs,
             })
         }
-        None => None,
+        (None, None) => None,
+        _ => panic!("{} and {} must be set together", cert_key, key_key),
+    }
+}
+
+pub fn resolve_durable() -> ConfigDurable {
+    ConfigDurable {
+        topics: env::list_or_empty("UNIT_DURABLE_TOPICS"),
+        max_len: env::value_or_default("UNIT_STREAM_MAX_LEN", 10000u64),
+        replay_limit: env::value_or_default("UNIT_REPLAY_LIMIT", 1000u64),
     }
 }
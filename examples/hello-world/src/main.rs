@@ -1,6 +1,9 @@
 use std::sync::Mutex;
 
-use unit::{log, Message};
+use unit::{
+    log, AuthorizeRequest, AuthorizeResponse, CrossbarContent, HttpRequest, HttpResponse, Message,
+    TopicParams,
+};
 
 unit::application! {
     name = "hello-world",
@@ -12,6 +15,34 @@ unit::data! { event_count: Mutex<u32> = Mutex::new(0) }
 async fn init() {
     let count = 12;
     log!("hello world! count = {c}", c = count);
+
+    let info = unit::connection::info();
+    log!(
+        "connection {} via {:?}, room = {:?}",
+        info.connection_id,
+        info.transport,
+        info.query_param("room")
+    );
+
+    if let Some(room) = info.query_param("room") {
+        unit::presence::join_room(&room);
+    }
+
+    // a reconnecting client passes the id of the last message it saw
+    if let Some(since) = info.query_param("since") {
+        for message in unit::crossbar::replay(unit::ReplayFrom::AfterId(since), vec![]) {
+            match message.content {
+                CrossbarContent::Text(text) => unit::client::send_text(text),
+                CrossbarContent::Binary(bytes) => unit::client::send_bytes(bytes),
+            }
+        }
+    }
+}
+
+#[unit::authorize]
+async fn authorize(request: AuthorizeRequest) -> AuthorizeResponse {
+    log!("authorizing connection from {:?}", request.remote_addr);
+    AuthorizeResponse::accept()
 }
 
 #[unit::cleanup]
@@ -29,3 +60,26 @@ async fn message(message: &Message) {
 async fn test(message: CrossbarContent) {
     log!("topic callback {} {:?}", event.topic, message);
 }
+
+#[unit::topic(name = "room.{id}.chat")]
+async fn room_chat(message: CrossbarContent, params: TopicParams) {
+    log!("room {:?} chat {:?}", params.get("id"), message);
+}
+
+#[unit::topic(name = "presence-join")]
+async fn presence_join(message: CrossbarContent) {
+    if let Some(presence) = unit::presence::from_event(message) {
+        log!("{} joined on {}", presence.connection_id, presence.node_id);
+    }
+}
+
+#[unit::request(name = "event-count")]
+async fn get_event_count(_payload: Vec<u8>) -> Vec<u8> {
+    let count = *event_count().lock().unwrap();
+    count.to_string().into_bytes()
+}
+
+#[unit::http(method = "GET", path = "/health")]
+async fn health(_request: HttpRequest) -> HttpResponse {
+    HttpResponse::text(200, "ok".to_owned())
+}
@@ -3,7 +3,7 @@ use unit_utils::Result;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut crossbar =
+    let crossbar =
         Crossbar::new("http://127.0.0.1:6448".to_owned(), "ilovecats".to_owned()).await?;
 
     loop {